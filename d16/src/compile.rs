@@ -0,0 +1,612 @@
+use crate::encode::{encode_packet_to_hex, EncodeErrorKind};
+use crate::packet::*;
+use num_traits::FromPrimitive;
+use std::error::Error;
+use std::fmt;
+
+// deeper expressions are rejected rather than risk overflowing the stack of
+// the recursive descent parser
+pub const MAX_EXPRESSION_DEPTH: usize = 128;
+
+// Fixed gives every packet the same version, Cycle numbers packets 0 to 7 in
+// the order they are written. Either is overridden by an @vN annotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionStrategy {
+    Fixed(u8),
+    Cycle,
+}
+
+// Alternate uses total lengths at even depths and subpacket counts at odd ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthModeStrategy {
+    Fixed(LengthMode),
+    Alternate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileOptions {
+    pub versions: VersionStrategy,
+    pub length_modes: LengthModeStrategy,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            versions: VersionStrategy::Fixed(0),
+            length_modes: LengthModeStrategy::Fixed(LengthMode::TotalLengthInBits),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    UnexpectedToken(String),
+    UnknownFunction(String),
+    LiteralOutOfRange,
+    VersionOutOfRange,
+    ChainedComparison,
+    WrongArity { expected: Arity, found: usize },
+    DepthLimitExceeded(usize),
+    Unencodable(EncodeErrorKind),
+}
+
+// offset is the byte offset into the expression where the problem starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub offset: usize,
+}
+
+impl fmt::Display for CompileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            CompileErrorKind::UnexpectedEnd => write!(f, "unexpected end of expression"),
+            CompileErrorKind::UnexpectedToken(token) => write!(f, "unexpected {:?}", token),
+            CompileErrorKind::UnknownFunction(name) => write!(f, "unknown function {:?}", name),
+            CompileErrorKind::LiteralOutOfRange => write!(f, "literal does not fit in 64 bits"),
+            CompileErrorKind::VersionOutOfRange => {
+                write!(f, "version does not fit in {} bits", PACKET_VERSION_SIZE)
+            }
+            CompileErrorKind::ChainedComparison => {
+                write!(f, "comparisons cannot be chained, add parentheses")
+            }
+            CompileErrorKind::WrongArity { expected, found } => {
+                write!(f, "takes {} operands but has {}", expected, found)
+            }
+            CompileErrorKind::DepthLimitExceeded(limit) => {
+                write!(f, "expression nests deeper than {} levels", limit)
+            }
+            CompileErrorKind::Unencodable(kind) => write!(f, "{}", kind),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl Error for CompileError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(String),
+    Name(String),
+    Version(String),
+    Plus,
+    Star,
+    Greater,
+    Less,
+    EqualEqual,
+    LeftParen,
+    RightParen,
+    Comma,
+}
+
+impl Token {
+    fn text(&self) -> String {
+        match self {
+            Token::Number(text) | Token::Name(text) => text.clone(),
+            Token::Version(text) => format!("@v{}", text),
+            Token::Plus => "+".to_string(),
+            Token::Star => "*".to_string(),
+            Token::Greater => ">".to_string(),
+            Token::Less => "<".to_string(),
+            Token::EqualEqual => "==".to_string(),
+            Token::LeftParen => "(".to_string(),
+            Token::RightParen => ")".to_string(),
+            Token::Comma => ",".to_string(),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, CompileError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+    let take_while = |chars: &mut std::iter::Peekable<std::str::CharIndices>,
+                      predicate: fn(char) -> bool| {
+        let mut text = String::new();
+        while let Some(&(_, c)) = chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+            text.push(c);
+            chars.next();
+        }
+        text
+    };
+
+    while let Some(&(offset, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '0'..='9' => Token::Number(take_while(&mut chars, |c| c.is_ascii_digit())),
+            'a'..='z' | 'A'..='Z' | '_' => Token::Name(take_while(&mut chars, |c| {
+                c.is_ascii_alphanumeric() || c == '_'
+            })),
+            '@' => {
+                chars.next();
+                match chars.next() {
+                    Some((_, 'v')) => {}
+                    Some((offset, c)) => {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::UnexpectedCharacter(c),
+                            offset,
+                        })
+                    }
+                    None => {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::UnexpectedEnd,
+                            offset: input.len(),
+                        })
+                    }
+                }
+                Token::Version(take_while(&mut chars, |c| c.is_ascii_digit()))
+            }
+            '=' => {
+                chars.next();
+                match chars.peek() {
+                    Some(&(_, '=')) => {}
+                    _ => {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::UnexpectedCharacter('='),
+                            offset,
+                        })
+                    }
+                }
+                chars.next();
+                Token::EqualEqual
+            }
+            _ => {
+                chars.next();
+                match c {
+                    '+' => Token::Plus,
+                    '*' => Token::Star,
+                    '>' => Token::Greater,
+                    '<' => Token::Less,
+                    '(' => Token::LeftParen,
+                    ')' => Token::RightParen,
+                    ',' => Token::Comma,
+                    _ => {
+                        return Err(CompileError {
+                            kind: CompileErrorKind::UnexpectedCharacter(c),
+                            offset,
+                        })
+                    }
+                }
+            }
+        };
+        tokens.push((token, offset));
+    }
+    Ok(tokens)
+}
+
+enum ExprKind {
+    Literal(Literal),
+    Operator(PacketOperatorType, Vec<Expr>),
+}
+
+struct Expr {
+    kind: ExprKind,
+    version: Option<u8>,
+    offset: usize,
+}
+
+// the call names accepted for each operator, min and max as they are rendered
+// plus the operator names themselves
+fn function(name: &str) -> Option<PacketOperatorType> {
+    match name {
+        "min" => Some(PacketOperatorType::Minimum),
+        "max" => Some(PacketOperatorType::Maximum),
+        _ => (0..1 << PACKET_TYPE_SIZE)
+            .filter_map(PacketOperatorType::from_u8)
+            .find(|operator_type| operator_type.name() == name),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    next: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(token, _offset)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map_or(self.end, |(_token, offset)| *offset)
+    }
+
+    fn error(&self, kind: CompileErrorKind) -> CompileError {
+        CompileError {
+            kind,
+            offset: self.offset(),
+        }
+    }
+
+    fn unexpected(&self) -> CompileError {
+        match self.peek() {
+            Some(token) => self.error(CompileErrorKind::UnexpectedToken(token.text())),
+            None => self.error(CompileErrorKind::UnexpectedEnd),
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), CompileError> {
+        if self.peek() != Some(&expected) {
+            return Err(self.unexpected());
+        }
+        self.next += 1;
+        Ok(())
+    }
+
+    fn operator(
+        operator_type: PacketOperatorType,
+        operands: Vec<Expr>,
+        offset: usize,
+    ) -> Result<Expr, CompileError> {
+        let arity = operator_type.arity();
+        if !arity.accepts(operands.len()) {
+            return Err(CompileError {
+                kind: CompileErrorKind::WrongArity {
+                    expected: arity,
+                    found: operands.len(),
+                },
+                offset,
+            });
+        }
+        Ok(Expr {
+            kind: ExprKind::Operator(operator_type, operands),
+            version: None,
+            offset,
+        })
+    }
+
+    // comparison := sum [ (> | < | ==) sum ]
+    fn parse_comparison(&mut self) -> Result<Expr, CompileError> {
+        let offset = self.offset();
+        let left = self.parse_chain(Token::Plus, PacketOperatorType::Sum)?;
+        let operator_type = match self.peek() {
+            Some(Token::Greater) => PacketOperatorType::GreaterThan,
+            Some(Token::Less) => PacketOperatorType::LessThan,
+            Some(Token::EqualEqual) => PacketOperatorType::EqualTo,
+            _ => return Ok(left),
+        };
+        self.next += 1;
+        let right = self.parse_chain(Token::Plus, PacketOperatorType::Sum)?;
+        if matches!(
+            self.peek(),
+            Some(Token::Greater | Token::Less | Token::EqualEqual)
+        ) {
+            return Err(self.error(CompileErrorKind::ChainedComparison));
+        }
+        Parser::operator(operator_type, vec![left, right], offset)
+    }
+
+    // sum := product { + product }, product := atom { * atom }, where a chain
+    // of the same operator becomes a single packet
+    fn parse_chain(
+        &mut self,
+        separator: Token,
+        operator_type: PacketOperatorType,
+    ) -> Result<Expr, CompileError> {
+        let offset = self.offset();
+        let parse_operand = |parser: &mut Parser| match operator_type {
+            PacketOperatorType::Sum => parser.parse_chain(Token::Star, PacketOperatorType::Product),
+            _ => parser.parse_atom(),
+        };
+
+        let mut operands = vec![parse_operand(self)?];
+        while self.peek() == Some(&separator) {
+            self.next += 1;
+            operands.push(parse_operand(self)?);
+        }
+        if operands.len() == 1 {
+            return Ok(operands.pop().unwrap());
+        }
+        Parser::operator(operator_type, operands, offset)
+    }
+
+    // atom := (number | ( comparison ) | name ( comparison {, comparison} )) [@vN]
+    fn parse_atom(&mut self) -> Result<Expr, CompileError> {
+        if self.depth == MAX_EXPRESSION_DEPTH {
+            return Err(self.error(CompileErrorKind::DepthLimitExceeded(MAX_EXPRESSION_DEPTH)));
+        }
+        self.depth += 1;
+
+        let offset = self.offset();
+        let mut expr = match self.peek().cloned() {
+            Some(Token::Number(digits)) => {
+                let literal: Literal = digits
+                    .parse()
+                    .map_err(|_| self.error(CompileErrorKind::LiteralOutOfRange))?;
+                self.next += 1;
+                Expr {
+                    kind: ExprKind::Literal(literal),
+                    version: None,
+                    offset,
+                }
+            }
+            Some(Token::LeftParen) => {
+                self.next += 1;
+                let expr = self.parse_comparison()?;
+                self.expect(Token::RightParen)?;
+                expr
+            }
+            Some(Token::Name(name)) => {
+                let operator_type = function(&name)
+                    .ok_or_else(|| self.error(CompileErrorKind::UnknownFunction(name)))?;
+                self.next += 1;
+                self.expect(Token::LeftParen)?;
+                let mut operands = vec![self.parse_comparison()?];
+                while self.peek() == Some(&Token::Comma) {
+                    self.next += 1;
+                    operands.push(self.parse_comparison()?);
+                }
+                self.expect(Token::RightParen)?;
+                Parser::operator(operator_type, operands, offset)?
+            }
+            _ => return Err(self.unexpected()),
+        };
+
+        if let Some(Token::Version(digits)) = self.peek() {
+            let version = digits
+                .parse::<u8>()
+                .ok()
+                .filter(|version| *version < 1 << PACKET_VERSION_SIZE)
+                .ok_or_else(|| self.error(CompileErrorKind::VersionOutOfRange))?;
+            expr.version = Some(version);
+            self.next += 1;
+        }
+
+        self.depth -= 1;
+        Ok(expr)
+    }
+}
+
+struct Builder {
+    options: CompileOptions,
+    npackets: usize,
+}
+
+impl Builder {
+    fn build(&mut self, expr: Expr, depth: usize) -> Result<Packet, CompileError> {
+        let packet_version = match (expr.version, self.options.versions) {
+            (Some(version), _) => version,
+            (None, VersionStrategy::Fixed(version)) => version,
+            (None, VersionStrategy::Cycle) => (self.npackets % (1 << PACKET_VERSION_SIZE)) as u8,
+        };
+        self.npackets += 1;
+
+        let (operator_type, operands) = match expr.kind {
+            ExprKind::Literal(literal) => return Ok(Packet::literal(packet_version, literal)),
+            ExprKind::Operator(operator_type, operands) => (operator_type, operands),
+        };
+        let mode = match self.options.length_modes {
+            LengthModeStrategy::Fixed(mode) => mode,
+            LengthModeStrategy::Alternate => match depth % 2 {
+                0 => LengthMode::TotalLengthInBits,
+                _ => LengthMode::NumberOfSubpackets,
+            },
+        };
+        let subpackets = operands
+            .into_iter()
+            .map(|operand| self.build(operand, depth + 1))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let packet = Packet::operator(packet_version, operator_type, mode, subpackets);
+        let length = match packet {
+            Packet::Operator(_packet_header, length, _) => length,
+            Packet::Literal(..) | Packet::Invalid { .. } => unreachable!(),
+        };
+        let unencodable = match length {
+            OperatorLength::TotalLengthInBits(total_length)
+                if total_length >= 1 << TOTAL_LENGTH_IN_BITS_SIZE =>
+            {
+                Some(EncodeErrorKind::TotalLengthOverflow(total_length))
+            }
+            OperatorLength::NumberOfSubpackets(nsubpackets)
+                if nsubpackets >= 1 << NUMBER_OF_SUBPACKETS_SIZE =>
+            {
+                Some(EncodeErrorKind::SubpacketCountOverflow(nsubpackets))
+            }
+            _ => None,
+        };
+        match unencodable {
+            Some(kind) => Err(CompileError {
+                kind: CompileErrorKind::Unencodable(kind),
+                offset: expr.offset,
+            }),
+            None => Ok(packet),
+        }
+    }
+}
+
+// Parses expressions like 1 + (2 * 3), min(4, 5) > max(6, 7)@v2 and
+// sum(8)@v1 into a packet tree. Comparisons bind loosest, then +, then *; the
+// operator names from PacketOperatorType can be called like min and max.
+pub fn compile_expression(input: &str, options: &CompileOptions) -> Result<Packet, CompileError> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        next: 0,
+        end: input.len(),
+        depth: 0,
+    };
+    let expr = parser.parse_comparison()?;
+    if parser.peek().is_some() {
+        return Err(parser.unexpected());
+    }
+
+    let mut builder = Builder {
+        options: *options,
+        npackets: 0,
+    };
+    builder.build(expr, 0)
+}
+
+pub fn compile_expression_to_hex(
+    input: &str,
+    options: &CompileOptions,
+) -> Result<String, CompileError> {
+    let packet = compile_expression(input, options)?;
+    // build has already rejected lengths that do not fit, but not a fixed
+    // version from options, which is reported at the start of the input
+    encode_packet_to_hex(&packet).map_err(|e| CompileError {
+        kind: CompileErrorKind::Unencodable(e.kind),
+        offset: 0,
+    })
+}
+
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+#[cfg(test)]
+use crate::eval::{compute_packet_result, compute_version_sum};
+#[cfg(test)]
+use crate::render::to_infix;
+
+#[test]
+fn test_compile_expression() {
+    let inputs = [
+        ("1 + 2", 3u64),
+        ("6 * 9", 54),
+        ("min(7, 8, 9)", 7),
+        ("max(7, 8, 9)", 9),
+        ("5 < 15", 1),
+        ("5 > 15", 0),
+        ("1 + 3 == 2 * 2", 1),
+        ("sum(8) + product(2, 3, 4)", 32),
+        ("greater_than(minimum(4, 5), maximum(1, 2))", 1),
+        ("((1 + 2) * 3)@v7", 9),
+        ("123456789", 123456789),
+    ];
+    for (input, expected) in inputs {
+        for options in [
+            CompileOptions::default(),
+            CompileOptions {
+                versions: VersionStrategy::Cycle,
+                length_modes: LengthModeStrategy::Alternate,
+            },
+        ] {
+            let hex = compile_expression_to_hex(input, &options).unwrap();
+            let (packet, _remaining_bits) = decode_packet_from_hex(&hex).unwrap();
+            assert_eq!(packet, compile_expression(input, &options).unwrap());
+            assert_eq!(compute_packet_result(&packet), Ok(Literal::from(expected)));
+        }
+    }
+
+    // rendered infix compiles back to the same tree
+    for input in [
+        "9C0141080250320F1802104A08",
+        "880086C3E88112",
+        "D8005AC2A8F0",
+    ] {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        let options = CompileOptions {
+            versions: VersionStrategy::Fixed(0),
+            length_modes: LengthModeStrategy::Fixed(match packet {
+                Packet::Operator(_packet_header, length, _) => length.mode(),
+                Packet::Literal(..) | Packet::Invalid { .. } => unreachable!(),
+            }),
+        };
+        let compiled = compile_expression(&to_infix(&packet, true), &options).unwrap();
+        assert_eq!(compute_version_sum(&compiled), compute_version_sum(&packet));
+        assert_eq!(to_infix(&compiled, true), to_infix(&packet, true));
+    }
+
+    let packet = compile_expression("1 + 2 + 3", &CompileOptions::default()).unwrap();
+    assert_eq!(packet.subpackets().len(), 3);
+    let packet = compile_expression("(1 + 2) + 3", &CompileOptions::default()).unwrap();
+    assert_eq!(packet.subpackets().len(), 2);
+}
+
+#[test]
+fn test_compile_errors() {
+    let inputs = [
+        ("1 +", CompileErrorKind::UnexpectedEnd, 3),
+        ("1 $ 2", CompileErrorKind::UnexpectedCharacter('$'), 2),
+        ("1 = 2", CompileErrorKind::UnexpectedCharacter('='), 2),
+        ("(1 + 2", CompileErrorKind::UnexpectedEnd, 6),
+        ("1 2", CompileErrorKind::UnexpectedToken("2".to_string()), 2),
+        (
+            "avg(1, 2)",
+            CompileErrorKind::UnknownFunction("avg".to_string()),
+            0,
+        ),
+        ("1 < 2 < 3", CompileErrorKind::ChainedComparison, 6),
+        ("1@v8", CompileErrorKind::VersionOutOfRange, 1),
+        (
+            "equal_to(1, 2, 3)",
+            CompileErrorKind::WrongArity {
+                expected: Arity::Exactly(2),
+                found: 3,
+            },
+            0,
+        ),
+    ];
+    for (input, kind, offset) in inputs {
+        assert_eq!(
+            compile_expression(input, &CompileOptions::default()),
+            Err(CompileError { kind, offset }),
+            "{}",
+            input
+        );
+    }
+
+    let deep = format!("{}1{}", "(".repeat(200), ")".repeat(200));
+    assert_eq!(
+        compile_expression(&deep, &CompileOptions::default())
+            .unwrap_err()
+            .kind,
+        CompileErrorKind::DepthLimitExceeded(MAX_EXPRESSION_DEPTH)
+    );
+    let options = CompileOptions {
+        versions: VersionStrategy::Fixed(8),
+        ..CompileOptions::default()
+    };
+    assert_eq!(
+        compile_expression_to_hex("1 + 2", &options),
+        Err(CompileError {
+            kind: CompileErrorKind::Unencodable(EncodeErrorKind::VersionOutOfRange(8)),
+            offset: 0,
+        })
+    );
+    #[cfg(not(feature = "bigint"))]
+    assert_eq!(
+        compile_expression("18446744073709551616", &CompileOptions::default())
+            .unwrap_err()
+            .kind,
+        CompileErrorKind::LiteralOutOfRange
+    );
+    #[cfg(feature = "bigint")]
+    assert!(compile_expression("18446744073709551616", &CompileOptions::default()).is_ok());
+}
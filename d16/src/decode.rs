@@ -0,0 +1,1042 @@
+use crate::packet::*;
+use bitvec::prelude::*;
+use num_traits::{FromPrimitive, Zero};
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+pub mod input;
+pub mod reader;
+pub mod source;
+pub mod stream;
+
+pub use crate::decode::source::{BitSource, SliceSource};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeErrorKind {
+    InvalidHexCharacter(char),
+    OddHexLength,
+    InvalidBase64Character(char),
+    InvalidBase64Length,
+    InvalidBitCharacter(char),
+    TruncatedHeader,
+    UnterminatedLiteral,
+    LiteralOverflow,
+    LengthOverrun { declared: usize, available: usize },
+    SubpacketCountMismatch { expected: usize, found: usize },
+    UnconsumedLengthBits(usize),
+    NonZeroPadding,
+    ExcessPadding(usize),
+    Io(io::ErrorKind),
+    DepthLimitExceeded(usize),
+    PacketLimitExceeded(usize),
+    LiteralGroupLimitExceeded(usize),
+    SubpacketLimitExceeded(usize),
+}
+
+// bit_offset is absolute from the start of the decoded buffer and path holds
+// the subpacket index at every nesting level, so [] is the outermost packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub kind: DecodeErrorKind,
+    pub bit_offset: usize,
+    pub path: Vec<usize>,
+}
+
+impl fmt::Display for DecodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeErrorKind::InvalidHexCharacter(c) => write!(f, "invalid hex character {:?}", c),
+            DecodeErrorKind::OddHexLength => write!(f, "odd number of hex digits"),
+            DecodeErrorKind::InvalidBase64Character(c) => {
+                write!(f, "invalid base64 character {:?}", c)
+            }
+            DecodeErrorKind::InvalidBase64Length => write!(f, "truncated base64"),
+            DecodeErrorKind::InvalidBitCharacter(c) => write!(f, "invalid bit character {:?}", c),
+            DecodeErrorKind::TruncatedHeader => write!(f, "truncated packet header"),
+            DecodeErrorKind::UnterminatedLiteral => write!(f, "unterminated literal"),
+            DecodeErrorKind::LiteralOverflow => write!(
+                f,
+                "literal does not fit in 64 bits, enable the bigint feature"
+            ),
+            DecodeErrorKind::LengthOverrun {
+                declared,
+                available,
+            } => write!(
+                f,
+                "length field declares {} bits but only {} are available",
+                declared, available
+            ),
+            DecodeErrorKind::SubpacketCountMismatch { expected, found } => write!(
+                f,
+                "expected {} subpackets but only found {}",
+                expected, found
+            ),
+            DecodeErrorKind::UnconsumedLengthBits(leftover) => {
+                write!(f, "{} bits left over inside operator", leftover)
+            }
+            DecodeErrorKind::NonZeroPadding => write!(f, "non-zero padding"),
+            DecodeErrorKind::ExcessPadding(padding) => {
+                write!(f, "{} bits of padding after packet", padding)
+            }
+            DecodeErrorKind::Io(kind) => write!(f, "I/O error: {}", kind),
+            DecodeErrorKind::DepthLimitExceeded(limit) => {
+                write!(f, "packets nested more than {} deep", limit)
+            }
+            DecodeErrorKind::PacketLimitExceeded(limit) => {
+                write!(f, "more than {} packets", limit)
+            }
+            DecodeErrorKind::LiteralGroupLimitExceeded(limit) => {
+                write!(f, "literal longer than {} groups", limit)
+            }
+            DecodeErrorKind::SubpacketLimitExceeded(limit) => {
+                write!(f, "operator with more than {} subpackets", limit)
+            }
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at bit {} (path {})",
+            self.kind,
+            self.bit_offset,
+            format_path(&self.path)
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
+// Lenient matches the original AoC decoder, which ignores whatever follows the
+// outermost packet and skips bits too short to be a packet at the end of a
+// total length operator. Strict reports both as errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Strictness {
+    #[default]
+    Lenient,
+    Strict,
+}
+
+// None means unlimited. Depth is counted from 0 for the outermost packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeLimits {
+    // Limited by default because dropping, cloning, comparing, debug printing
+    // or serializing a Packet tree recurses once per level, and can overflow
+    // the stack for trees much deeper than that. Apart from fold_recursive,
+    // everything else in the crate that walks a Packet tree, encoding
+    // included, does so without recursing. The expression compiler recurses
+    // too, but never deeper than MAX_EXPRESSION_DEPTH.
+    pub max_depth: Option<usize>,
+    pub max_packets: Option<usize>,
+    pub max_literal_groups: Option<usize>,
+    pub max_subpackets: Option<usize>,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits {
+            max_depth: Some(1024),
+            max_packets: None,
+            max_literal_groups: None,
+            max_subpackets: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub strictness: Strictness,
+    pub limits: DecodeLimits,
+}
+
+impl DecodeOptions {
+    pub fn strict() -> DecodeOptions {
+        DecodeOptions {
+            strictness: Strictness::Strict,
+            ..DecodeOptions::default()
+        }
+    }
+}
+
+// hex digits come in pairs, so a transmission is padded with zeros to the
+// next nibble and then to the end of that byte
+pub const PADDING_ALIGNMENT: usize = 8;
+
+enum PendingLength {
+    TotalLengthInBits {
+        total_length: usize,
+        end: usize,
+        outer_limit: Option<usize>,
+    },
+    NumberOfSubpackets(usize),
+}
+
+// an operator whose subpackets are still being decoded
+struct PendingOperator {
+    packet_header: PacketHeader,
+    length: PendingLength,
+    subpackets: Vec<Packet>,
+}
+
+enum Begun {
+    Complete(Packet),
+    Operator(PendingOperator),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Version,
+    TypeId,
+    LiteralContinuation,
+    LiteralGroup,
+    LengthTypeId,
+    TotalLength,
+    SubpacketCount,
+    // bits at the end of a total length operator too short to be a packet
+    LengthSlack,
+    Padding,
+}
+
+// raw holds the field's bits as 0s and 1s, value the same bits as a number
+// for every field that fits in a usize
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub kind: FieldKind,
+    pub bit_range: Range<usize>,
+    pub depth: usize,
+    pub raw: String,
+    pub value: usize,
+}
+
+impl FieldKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FieldKind::Version => "version",
+            FieldKind::TypeId => "type ID",
+            FieldKind::LiteralContinuation => "continuation",
+            FieldKind::LiteralGroup => "literal group",
+            FieldKind::LengthTypeId => "length type ID",
+            FieldKind::TotalLength => "total length",
+            FieldKind::SubpacketCount => "subpacket count",
+            FieldKind::LengthSlack => "length slack",
+            FieldKind::Padding => "padding",
+        }
+    }
+}
+
+struct Decoder<'s, 'o, S: BitSource> {
+    source: &'s mut S,
+    path: Vec<usize>,
+    options: &'o DecodeOptions,
+    // end of the innermost total length operator being decoded, subpackets
+    // cannot read past it
+    limit: Option<usize>,
+    npackets: usize,
+    // every field read so far, only kept when disassembling
+    fields: Option<Vec<Field>>,
+    // where each packet started in the order they began, only kept when
+    // asked for
+    starts: Option<Vec<usize>>,
+    // whether errors become invalid packets rather than ending the decode
+    recovering: bool,
+}
+
+impl<'s, 'o, S: BitSource> Decoder<'s, 'o, S> {
+    fn new(source: &'s mut S, options: &'o DecodeOptions) -> Decoder<'s, 'o, S> {
+        Decoder {
+            source,
+            path: Vec::new(),
+            options,
+            limit: None,
+            npackets: 0,
+            fields: None,
+            starts: None,
+            recovering: false,
+        }
+    }
+
+    fn position(&self) -> usize {
+        self.source.position()
+    }
+
+    // called once as each packet begins, including invalid ones
+    fn record_start(&mut self, start: usize) {
+        if let Some(starts) = self.starts.as_mut() {
+            starts.push(start);
+        }
+    }
+
+    fn error(&self, kind: DecodeErrorKind, bit_offset: usize) -> DecodeError {
+        DecodeError {
+            kind,
+            bit_offset,
+            path: self.path.clone(),
+        }
+    }
+
+    fn available(&mut self, nbits: usize) -> Result<usize, DecodeError> {
+        let available = self.source.available(nbits)?;
+        Ok(match self.limit {
+            Some(limit) => available.min(limit - self.position()),
+            None => available,
+        })
+    }
+
+    // callers make sure that nbits are available first, fields longer than a
+    // usize only come from skipped bits whose value is never used
+    fn read(&mut self, kind: FieldKind, nbits: usize) -> usize {
+        let start = self.position();
+        let mut raw = String::new();
+        let mut value: usize = 0;
+        for _ in 0..nbits {
+            let bit = self.source.take_bit();
+            value = value.wrapping_shl(1) | bit as usize;
+            if self.fields.is_some() {
+                raw.push(if bit { '1' } else { '0' });
+            }
+        }
+
+        let depth = self.path.len();
+        if let Some(fields) = self.fields.as_mut() {
+            fields.push(Field {
+                kind,
+                bit_range: start..start + nbits,
+                depth,
+                raw,
+                value,
+            });
+        }
+        value
+    }
+
+    // the literal and the number of groups it was sent with
+    fn decode_literal(&mut self) -> Result<(Literal, usize), DecodeError> {
+        let start = self.position();
+        let mut result = Literal::zero();
+        let mut ngroups = 0;
+
+        loop {
+            if self.available(LITERAL_GROUP_SIZE + 1)? < LITERAL_GROUP_SIZE + 1 {
+                return Err(self.error(DecodeErrorKind::UnterminatedLiteral, start));
+            }
+            ngroups += 1;
+            if let Some(max_literal_groups) = self.options.limits.max_literal_groups {
+                if ngroups > max_literal_groups {
+                    let kind = DecodeErrorKind::LiteralGroupLimitExceeded(max_literal_groups);
+                    return Err(self.error(kind, start));
+                }
+            }
+            let more_groups = self.read(FieldKind::LiteralContinuation, 1) == 1;
+            let group = self.read(FieldKind::LiteralGroup, LITERAL_GROUP_SIZE) as u8;
+
+            #[cfg(not(feature = "bigint"))]
+            if result.leading_zeros() < LITERAL_GROUP_SIZE as u32 {
+                return Err(self.error(DecodeErrorKind::LiteralOverflow, start));
+            }
+            result = result << LITERAL_GROUP_SIZE | Literal::from(group);
+
+            if !more_groups {
+                return Ok((result, ngroups));
+            }
+        }
+    }
+
+    fn begin_operator_for_bits(&mut self) -> Result<PendingLength, DecodeError> {
+        let start = self.position();
+        if self.available(TOTAL_LENGTH_IN_BITS_SIZE)? < TOTAL_LENGTH_IN_BITS_SIZE {
+            return Err(self.error(DecodeErrorKind::TruncatedHeader, start));
+        }
+        let total_length = self.read(FieldKind::TotalLength, TOTAL_LENGTH_IN_BITS_SIZE);
+        let available = self.available(total_length)?;
+        if total_length > available {
+            let kind = DecodeErrorKind::LengthOverrun {
+                declared: total_length,
+                available,
+            };
+            return Err(self.error(kind, start));
+        }
+        let end = self.position() + total_length;
+        let outer_limit = self.limit.replace(end);
+
+        Ok(PendingLength::TotalLengthInBits {
+            total_length,
+            end,
+            outer_limit,
+        })
+    }
+
+    fn begin_operator_by_subpackets(&mut self) -> Result<PendingLength, DecodeError> {
+        let start = self.position();
+        if self.available(NUMBER_OF_SUBPACKETS_SIZE)? < NUMBER_OF_SUBPACKETS_SIZE {
+            return Err(self.error(DecodeErrorKind::TruncatedHeader, start));
+        }
+        let nsubpackets = self.read(FieldKind::SubpacketCount, NUMBER_OF_SUBPACKETS_SIZE);
+        if let Some(max_subpackets) = self.options.limits.max_subpackets {
+            if nsubpackets > max_subpackets {
+                let kind = DecodeErrorKind::SubpacketLimitExceeded(max_subpackets);
+                return Err(self.error(kind, start));
+            }
+        }
+
+        Ok(PendingLength::NumberOfSubpackets(nsubpackets))
+    }
+
+    // reads a packet header and either the whole literal or just the length
+    // field of an operator, leaving its subpackets to the caller
+    fn begin_packet(&mut self) -> Result<Begun, DecodeError> {
+        let start = self.position();
+        if self.available(MIN_PACKET_SIZE)? < MIN_PACKET_SIZE {
+            return Err(self.error(DecodeErrorKind::TruncatedHeader, start));
+        }
+        if let Some(max_depth) = self.options.limits.max_depth {
+            if self.path.len() > max_depth {
+                let kind = DecodeErrorKind::DepthLimitExceeded(max_depth);
+                return Err(self.error(kind, start));
+            }
+        }
+        self.npackets += 1;
+        if let Some(max_packets) = self.options.limits.max_packets {
+            if self.npackets > max_packets {
+                let kind = DecodeErrorKind::PacketLimitExceeded(max_packets);
+                return Err(self.error(kind, start));
+            }
+        }
+
+        let packet_version = self.read(FieldKind::Version, PACKET_VERSION_SIZE) as u8;
+        let packet_type = self.read(FieldKind::TypeId, PACKET_TYPE_SIZE) as u8;
+        let packet_header = PacketHeader {
+            packet_version,
+            packet_type,
+        };
+
+        match FromPrimitive::from_u8(packet_type) {
+            Some(PacketType::Literal) => {
+                let (literal, ngroups) = self.decode_literal()?;
+                Ok(Begun::Complete(Packet::Literal(
+                    packet_header,
+                    literal,
+                    ngroups,
+                )))
+            }
+            None => {
+                if self.available(1)? < 1 {
+                    return Err(self.error(DecodeErrorKind::TruncatedHeader, self.position()));
+                }
+                let length = if self.read(FieldKind::LengthTypeId, 1) == 1 {
+                    self.begin_operator_by_subpackets()?
+                } else {
+                    self.begin_operator_for_bits()?
+                };
+                Ok(Begun::Operator(PendingOperator {
+                    packet_header,
+                    length,
+                    subpackets: Vec::new(),
+                }))
+            }
+        }
+    }
+
+    // whether the operator on top of the stack has another subpacket to come
+    fn expects_subpacket(&mut self, operator: &PendingOperator) -> Result<bool, DecodeError> {
+        let more = match operator.length {
+            PendingLength::TotalLengthInBits { end, .. } => {
+                let more = self.available(MIN_PACKET_SIZE)? >= MIN_PACKET_SIZE;
+                let leftover = end - self.position();
+                if !more && self.options.strictness == Strictness::Strict && leftover > 0 {
+                    let kind = DecodeErrorKind::UnconsumedLengthBits(leftover);
+                    return Err(self.error(kind, self.position()));
+                }
+                more
+            }
+            PendingLength::NumberOfSubpackets(nsubpackets) => {
+                let found = operator.subpackets.len();
+                if found < nsubpackets && self.available(MIN_PACKET_SIZE)? < MIN_PACKET_SIZE {
+                    let kind = DecodeErrorKind::SubpacketCountMismatch {
+                        expected: nsubpackets,
+                        found,
+                    };
+                    return Err(self.error(kind, self.position()));
+                }
+                found < nsubpackets
+            }
+        };
+
+        if let Some(max_subpackets) = self.options.limits.max_subpackets {
+            if more && operator.subpackets.len() >= max_subpackets {
+                let kind = DecodeErrorKind::SubpacketLimitExceeded(max_subpackets);
+                return Err(self.error(kind, self.position()));
+            }
+        }
+        Ok(more)
+    }
+
+    // any bits left over in a total length operator have been checked by
+    // expects_subpacket
+    fn finish_operator(&mut self, operator: PendingOperator) -> Packet {
+        let length = match operator.length {
+            PendingLength::TotalLengthInBits {
+                total_length,
+                end,
+                outer_limit,
+            } => {
+                if end > self.position() {
+                    self.read(FieldKind::LengthSlack, end - self.position());
+                }
+                self.limit = outer_limit;
+                OperatorLength::TotalLengthInBits(total_length)
+            }
+            PendingLength::NumberOfSubpackets(nsubpackets) => {
+                OperatorLength::NumberOfSubpackets(nsubpackets)
+            }
+        };
+        Packet::Operator(operator.packet_header, length, operator.subpackets)
+    }
+
+    // Turns everything from start to the end of the innermost total length
+    // operator into an invalid packet, since that is the next place where a
+    // packet boundary is known. Operators nested inside that one are closed
+    // with the subpackets they have so far. Without a total length operator
+    // to resync at, the rest of the input is invalid.
+    fn recover(
+        &mut self,
+        stack: &mut Vec<PendingOperator>,
+        start: usize,
+        reason: DecodeErrorKind,
+    ) -> Result<Packet, DecodeError> {
+        let resync = stack.iter().rposition(|operator| {
+            matches!(operator.length, PendingLength::TotalLengthInBits { .. })
+        });
+        // available stops at the limit, which is the end of that operator
+        loop {
+            let nbits = self.available(PADDING_ALIGNMENT)?;
+            if nbits == 0 {
+                break;
+            }
+            for _ in 0..nbits {
+                self.source.take_bit();
+            }
+        }
+
+        let mut packet = Packet::Invalid {
+            bit_range: start..self.position(),
+            reason,
+        };
+        let keep = resync.map_or(0, |i| i + 1);
+        while stack.len() > keep {
+            let mut operator = stack.pop().expect("stack is longer than keep");
+            operator.subpackets.push(packet);
+            self.path.pop();
+            packet = self.finish_operator(operator);
+        }
+        Ok(packet)
+    }
+
+    // Operators whose subpackets are still being decoded are kept on an
+    // explicit stack rather than the call stack, so nesting depth is only
+    // bounded by DecodeLimits::max_depth.
+    fn decode_packet(&mut self) -> Result<Packet, DecodeError> {
+        let mut stack: Vec<PendingOperator> = Vec::new();
+
+        loop {
+            let start = self.position();
+            self.record_start(start);
+            let begun = match self.begin_packet() {
+                Err(e) if self.recovering => {
+                    Begun::Complete(self.recover(&mut stack, start, e.kind)?)
+                }
+                begun => begun?,
+            };
+            let mut completed = match begun {
+                Begun::Complete(packet) => Some(packet),
+                Begun::Operator(operator) => {
+                    stack.push(operator);
+                    None
+                }
+            };
+
+            loop {
+                let mut operator = match stack.pop() {
+                    Some(operator) => operator,
+                    None => return Ok(completed.expect("no packet decoded")),
+                };
+                if let Some(packet) = completed.take() {
+                    operator.subpackets.push(packet);
+                    self.path.pop();
+                }
+
+                match self.expects_subpacket(&operator) {
+                    Ok(true) => {
+                        self.path.push(operator.subpackets.len());
+                        stack.push(operator);
+                        break;
+                    }
+                    Ok(false) => completed = Some(self.finish_operator(operator)),
+                    // the problem is where the next subpacket would start
+                    Err(e) if self.recovering => {
+                        self.path.push(operator.subpackets.len());
+                        stack.push(operator);
+                        let start = self.position();
+                        self.record_start(start);
+                        completed = Some(self.recover(&mut stack, start, e.kind)?);
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    }
+}
+
+// decodes like decode_packet_from_source while recording every field read,
+// which are returned even when decoding fails part way through
+pub(crate) fn decode_fields_from_source<S: BitSource>(
+    source: &mut S,
+    options: &DecodeOptions,
+) -> (Result<Packet, DecodeError>, Vec<Field>) {
+    let mut decoder = Decoder::new(source, options);
+    decoder.fields = Some(Vec::new());
+    let result = decoder.decode_packet();
+    (result, decoder.fields.unwrap_or_default())
+}
+
+pub fn decode_packet_from_source<S: BitSource>(
+    source: &mut S,
+    options: &DecodeOptions,
+) -> Result<Packet, DecodeError> {
+    Decoder::new(source, options).decode_packet()
+}
+
+pub fn decode_packet_with<'a>(
+    packet_bits: &'a D16BitSlice,
+    options: &DecodeOptions,
+) -> Result<(Packet, &'a D16BitSlice), DecodeError> {
+    let mut source = SliceSource::new(packet_bits);
+    let packet = decode_packet_from_source(&mut source, options)?;
+    Ok((packet, source.remaining_bits()))
+}
+
+pub fn decode_packet(packet_bits: &D16BitSlice) -> Result<(Packet, &D16BitSlice), DecodeError> {
+    decode_packet_with(packet_bits, &DecodeOptions::default())
+}
+
+// decodes while recording where every packet started, which is where
+// explain and queries report packets to be
+pub(crate) fn decode_offsets_from_source<S: BitSource>(
+    source: &mut S,
+    options: &DecodeOptions,
+    recovering: bool,
+) -> Result<(Packet, PacketOffsets), DecodeError> {
+    let mut decoder = Decoder::new(source, options);
+    decoder.starts = Some(Vec::new());
+    decoder.recovering = recovering;
+    let packet = decoder.decode_packet()?;
+    let offsets = PacketOffsets::from_starts(&packet, decoder.starts.unwrap_or_default());
+    Ok((packet, offsets))
+}
+
+pub fn decode_packet_with_offsets<'a>(
+    packet_bits: &'a D16BitSlice,
+    options: &DecodeOptions,
+) -> Result<(Packet, PacketOffsets, &'a D16BitSlice), DecodeError> {
+    let mut source = SliceSource::new(packet_bits);
+    let (packet, offsets) = decode_offsets_from_source(&mut source, options, false)?;
+    Ok((packet, offsets, source.remaining_bits()))
+}
+
+// Decodes like decode_packet_from_source, except that bits which cannot be
+// decoded become Packet::Invalid and decoding carries on after them, see
+// Decoder::recover. Only errors from the source itself are returned.
+pub fn decode_packet_recovering_from_source<S: BitSource>(
+    source: &mut S,
+    options: &DecodeOptions,
+) -> Result<Packet, DecodeError> {
+    let mut decoder = Decoder::new(source, options);
+    decoder.recovering = true;
+    decoder.decode_packet()
+}
+
+pub fn decode_packet_recovering_with<'a>(
+    packet_bits: &'a D16BitSlice,
+    options: &DecodeOptions,
+) -> (Packet, &'a D16BitSlice) {
+    let mut source = SliceSource::new(packet_bits);
+    let packet = decode_packet_recovering_from_source(&mut source, options)
+        .expect("slices cannot fail to read");
+    (packet, source.remaining_bits())
+}
+
+pub fn decode_packet_recovering(packet_bits: &D16BitSlice) -> (Packet, &D16BitSlice) {
+    decode_packet_recovering_with(packet_bits, &DecodeOptions::default())
+}
+
+pub fn decode_packet_recovering_with_offsets<'a>(
+    packet_bits: &'a D16BitSlice,
+    options: &DecodeOptions,
+) -> (Packet, PacketOffsets, &'a D16BitSlice) {
+    let mut source = SliceSource::new(packet_bits);
+    let (packet, offsets) =
+        decode_offsets_from_source(&mut source, options, true).expect("slices cannot fail to read");
+    (packet, offsets, source.remaining_bits())
+}
+
+// checks that the bits following a packet are nothing but zero padding, with
+// bit offsets in the error counted from the start of root
+pub fn check_padding(root: &D16BitSlice, remaining_bits: &D16BitSlice) -> Result<(), DecodeError> {
+    let error = |kind| DecodeError {
+        kind,
+        bit_offset: root.offset_from(remaining_bits) as usize,
+        path: Vec::new(),
+    };
+    if remaining_bits.len() >= PADDING_ALIGNMENT {
+        return Err(error(DecodeErrorKind::ExcessPadding(remaining_bits.len())));
+    }
+    if remaining_bits.any() {
+        return Err(error(DecodeErrorKind::NonZeroPadding));
+    }
+    Ok(())
+}
+
+// whitespace is skipped so that transmissions can be split across lines
+pub fn decode_hex(input: &str) -> Result<Vec<u8>, DecodeError> {
+    let digits: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    hex::decode(&digits).map_err(|e| {
+        let (kind, bit_offset) = match e {
+            hex::FromHexError::InvalidHexCharacter { c, index } => {
+                (DecodeErrorKind::InvalidHexCharacter(c), index * 4)
+            }
+            _ => (DecodeErrorKind::OddHexLength, digits.len() * 4),
+        };
+        DecodeError {
+            kind,
+            bit_offset,
+            path: Vec::new(),
+        }
+    })
+}
+
+pub fn decode_packet_from_hex_with(
+    input: &str,
+    options: &DecodeOptions,
+) -> Result<(Packet, BitVec<Msb0>), DecodeError> {
+    let decoded = decode_hex(input)?;
+    let bits = decoded.view_bits::<Msb0>();
+    let (packet, remaining_bits) = decode_packet_with(bits, options)?;
+    if options.strictness == Strictness::Strict {
+        check_padding(bits, remaining_bits)?;
+    }
+    let remaining_bits_bv = remaining_bits.iter().collect();
+    Ok((packet, remaining_bits_bv))
+}
+
+pub fn decode_packet_from_hex(input: &str) -> Result<(Packet, BitVec<Msb0>), DecodeError> {
+    decode_packet_from_hex_with(input, &DecodeOptions::default())
+}
+
+#[test]
+fn test_decode_literal_packet() {
+    let (packet, remaining_bits) = decode_packet_from_hex("D2FE28").unwrap();
+    assert_eq!(packet, Packet::literal(6, 2021u16));
+    assert_eq!(remaining_bits.len(), 3);
+}
+
+#[test]
+fn test_decode_operator_packets() {
+    for input in ["38006F45291200", "EE00D40C823060"] {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        match packet {
+            Packet::Operator(_packet_header, _length, subpackets) => {
+                let literals: Vec<String> = subpackets
+                    .iter()
+                    .map(|subpacket| match subpacket {
+                        Packet::Literal(_packet_header, literal, _ngroups) => literal.to_string(),
+                        _ => panic!("expected literal subpacket"),
+                    })
+                    .collect();
+                assert!(literals == ["10", "20"] || literals == ["1", "2", "3"]);
+            }
+            _ => panic!("expected operator packet"),
+        }
+    }
+}
+
+#[test]
+fn test_decode_errors() {
+    let error_kind = |input| decode_packet_from_hex(input).unwrap_err().kind;
+
+    let error = decode_packet_from_hex("D2FZ28").unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidHexCharacter('Z'));
+    assert_eq!(error.bit_offset, 12);
+    assert_eq!(error_kind("D2FE2"), DecodeErrorKind::OddHexLength);
+    assert_eq!(error_kind("D2FE"), DecodeErrorKind::UnterminatedLiteral);
+    assert_eq!(error_kind("38"), DecodeErrorKind::TruncatedHeader);
+    assert_eq!(
+        error_kind("38006F4529"),
+        DecodeErrorKind::LengthOverrun {
+            declared: 27,
+            available: 18
+        }
+    );
+
+    // third literal of the three in EE00D40C823060 cut off mid-group
+    let error = decode_packet_from_hex("EE00D40C8230").unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnterminatedLiteral);
+    assert_eq!(error.path, vec![2]);
+    assert_eq!(error.bit_offset, 46);
+
+    let error = decode_packet_from_hex("EE00D40C82").unwrap_err();
+    assert_eq!(
+        error.kind,
+        DecodeErrorKind::SubpacketCountMismatch {
+            expected: 3,
+            found: 2
+        }
+    );
+}
+
+#[test]
+fn test_strict_decoding() {
+    let strict = DecodeOptions::strict();
+    for input in ["D2FE28", "38006F45291200", "EE00D40C823060"] {
+        assert!(decode_packet_from_hex_with(input, &strict).is_ok());
+    }
+
+    let error = decode_packet_from_hex_with("D2FE2800", &strict).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::ExcessPadding(11));
+    assert_eq!(error.bit_offset, 21);
+    let error = decode_packet_from_hex_with("D2FE29", &strict).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::NonZeroPadding);
+
+    // 38006F45291200 with the total length bumped from 27 to 29 bits
+    let input = "38007545291200";
+    assert!(decode_packet_from_hex(input).is_ok());
+    let error = decode_packet_from_hex_with(input, &strict).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnconsumedLengthBits(2));
+    assert_eq!(error.bit_offset, 49);
+}
+
+#[test]
+fn test_decode_long_literal() {
+    // literal packet with 17 groups of 0xf
+    let mut bits: BitVec<Msb0, u8> = [1, 1, 0, 1, 0, 0].iter().map(|&b| b == 1).collect();
+    for i in 0..17 {
+        bits.push(i != 16);
+        bits.extend([true; LITERAL_GROUP_SIZE]);
+    }
+
+    let result = decode_packet(&bits);
+    #[cfg(not(feature = "bigint"))]
+    {
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, DecodeErrorKind::LiteralOverflow);
+        assert_eq!(error.bit_offset, 6);
+    }
+    #[cfg(feature = "bigint")]
+    {
+        let (packet, _remaining_bits) = result.unwrap();
+        let expected = (Literal::from(1u8) << 68) - 1u8;
+        assert_eq!(packet, Packet::literal(6, expected));
+        assert_eq!(crate::encode::encode_packet(&packet).unwrap(), bits);
+    }
+}
+
+#[cfg(test)]
+fn nested_sums(depth: usize) -> BitVec<Msb0, u8> {
+    // depth single child sums by subpacket count around a literal 1
+    let mut bits = BitVec::<Msb0, u8>::new();
+    let mut push = |value: usize, width: usize| {
+        for i in (0..width).rev() {
+            bits.push((value >> i) & 1 == 1);
+        }
+    };
+    for _ in 0..depth {
+        push(0, PACKET_VERSION_SIZE + PACKET_TYPE_SIZE);
+        push(1, 1);
+        push(1, NUMBER_OF_SUBPACKETS_SIZE);
+    }
+    push(
+        PacketType::Literal as usize,
+        PACKET_VERSION_SIZE + PACKET_TYPE_SIZE,
+    );
+    push(1, LITERAL_GROUP_SIZE + 1);
+    bits
+}
+
+// a sum of 5, sent as the groups 0000, 0000 and 0101, and a nested sum, for
+// tests that need a literal with leading zero groups at a known offset
+#[cfg(test)]
+pub(crate) fn padded_literal_sum() -> BitVec<Msb0, u8> {
+    let fields = "000 000 1 00000000010 \
+                  100 100 10000 10000 00101 \
+                  000 000 1 00000000001 000 100 00001";
+    fields
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c == '1')
+        .collect()
+}
+
+#[test]
+fn test_deep_nesting() {
+    let depth = 100_000;
+    let bits = nested_sums(depth);
+
+    let error = decode_packet(&bits).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::DepthLimitExceeded(1024));
+    assert_eq!(error.path.len(), 1025);
+    assert_eq!(error.bit_offset, 1025 * 18);
+
+    let mut options = DecodeOptions::default();
+    options.limits.max_depth = None;
+    let (packet, offsets, remaining_bits) = decode_packet_with_offsets(&bits, &options).unwrap();
+    assert!(remaining_bits.is_empty());
+    assert_eq!(crate::eval::compute_version_sum(&packet), 0);
+    assert_eq!(
+        crate::eval::compute_packet_result(&packet).unwrap(),
+        Literal::from(1u8)
+    );
+    assert!(crate::validate::validate(&packet).is_empty());
+    assert!(packet.length_mismatches().is_empty());
+    assert_eq!(packet.bit_len(), bits.len());
+    assert_eq!(crate::encode::encode_packet(&packet).unwrap(), bits);
+    let stats = crate::stats::packet_stats(&packet, bits.len());
+    assert_eq!(stats.bits.total(), bits.len());
+    assert_eq!(offsets, PacketOffsets::from_layout(&packet, 0));
+    assert_eq!(offsets.nth(depth), Some(depth * 18));
+    let query = crate::query::Query::parse("/0/0").unwrap();
+    assert_eq!(
+        query.select_with_offsets(&packet, &offsets).unwrap()[0].bit_offset,
+        36
+    );
+    let query = crate::query::Query::parse("//literal").unwrap();
+    let selected = query.select_with_offsets(&packet, &offsets).unwrap();
+    assert_eq!(selected.len(), 1);
+    assert_eq!(selected[0].path.len(), depth);
+    assert_eq!(selected[0].bit_offset, depth * 18);
+
+    // unwind the tree by hand, dropping it would recurse once per level
+    let mut packet = packet;
+    while let Packet::Operator(_packet_header, _length, mut subpackets) = packet {
+        packet = subpackets.pop().unwrap();
+    }
+}
+
+#[test]
+fn test_decode_limits() {
+    let decode_with_limits = |input, limits| {
+        let options = DecodeOptions {
+            limits,
+            ..DecodeOptions::default()
+        };
+        decode_packet_from_hex_with(input, &options).map(|(packet, _remaining_bits)| packet)
+    };
+    let unlimited = DecodeLimits {
+        max_depth: None,
+        max_packets: None,
+        max_literal_groups: None,
+        max_subpackets: None,
+    };
+
+    // A0016C880162017C3686B18A3D4780 has literals at depth 3 and 8 packets,
+    // with 5 subpackets in the innermost sum
+    let input = "A0016C880162017C3686B18A3D4780";
+    assert!(decode_with_limits(input, unlimited).is_ok());
+
+    let limits = DecodeLimits {
+        max_depth: Some(2),
+        ..unlimited
+    };
+    let error = decode_with_limits(input, limits).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::DepthLimitExceeded(2));
+    assert_eq!(error.path, vec![0, 0, 0]);
+
+    let limits = DecodeLimits {
+        max_packets: Some(7),
+        ..unlimited
+    };
+    let error = decode_with_limits(input, limits).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::PacketLimitExceeded(7));
+
+    let limits = DecodeLimits {
+        max_subpackets: Some(4),
+        ..unlimited
+    };
+    let error = decode_with_limits(input, limits).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::SubpacketLimitExceeded(4));
+
+    let limits = DecodeLimits {
+        max_literal_groups: Some(2),
+        ..unlimited
+    };
+    let error = decode_with_limits("D2FE28", limits).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::LiteralGroupLimitExceeded(2));
+    assert_eq!(error.bit_offset, 6);
+}
+
+#[test]
+fn test_decode_recovering() {
+    let decode = |input| {
+        let bits = decode_hex(input).unwrap();
+        let (packet, _remaining_bits) = decode_packet_recovering(bits.view_bits());
+        packet
+    };
+    let sum = |subpackets| {
+        Packet::operator(
+            0,
+            PacketOperatorType::Sum,
+            LengthMode::TotalLengthInBits,
+            subpackets,
+        )
+    };
+    let invalid = |bit_range, reason| Packet::Invalid { bit_range, reason };
+
+    // (1 + 2) * (3 + 4) with the continuation bit of the 2 set, so that the
+    // literal runs into the end of its sum and decoding resyncs there
+    let input = "0401600001610224800016106210";
+    assert!(decode_packet_from_hex(input).is_err());
+    let packet = decode(input);
+    let expected = Packet::operator(
+        0,
+        PacketOperatorType::Product,
+        LengthMode::TotalLengthInBits,
+        vec![
+            sum(vec![
+                Packet::literal(0, 1u8),
+                invalid(55..66, DecodeErrorKind::UnterminatedLiteral),
+            ]),
+            sum(vec![Packet::literal(0, 3u8), Packet::literal(0, 4u8)]),
+        ],
+    );
+    assert_eq!(packet, expected);
+    assert_eq!(packet.bit_len(), 110);
+    let bits = decode_hex(input).unwrap();
+    let (_packet, offsets, _remaining_bits) =
+        decode_packet_recovering_with_offsets(bits.view_bits(), &DecodeOptions::default());
+    assert_eq!(offsets.get(&[0, 1]), Some(55));
+    assert_eq!(offsets, PacketOffsets::from_layout(&packet, 0));
+
+    // without a total length operator to resync at the rest is invalid
+    let packet = decode("EE00D40C8230");
+    assert_eq!(
+        packet.subpackets()[2],
+        invalid(40..48, DecodeErrorKind::UnterminatedLiteral)
+    );
+    assert_eq!(
+        decode("38"),
+        invalid(0..8, DecodeErrorKind::TruncatedHeader)
+    );
+
+    // bits left over in a total length operator are invalid when strict
+    let bits = decode_hex("38007545291200").unwrap();
+    let (packet, _remaining_bits) =
+        decode_packet_recovering_with(bits.view_bits(), &DecodeOptions::strict());
+    assert_eq!(
+        packet.subpackets()[2],
+        invalid(49..51, DecodeErrorKind::UnconsumedLengthBits(2))
+    );
+    for input in ["D2FE28", "38006F45291200", "EE00D40C823060"] {
+        assert_eq!(decode(input), decode_packet_from_hex(input).unwrap().0);
+    }
+}
@@ -0,0 +1,210 @@
+use crate::decode::*;
+use base64::Engine;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Hex,
+    Base64,
+    // a string of 0 and 1 characters, one per bit
+    Bits,
+    // the transmission bytes themselves
+    Binary,
+}
+
+impl InputFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            InputFormat::Hex => "hex",
+            InputFormat::Base64 => "base64",
+            InputFormat::Bits => "bits",
+            InputFormat::Binary => "binary",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InputFormat> {
+        [
+            InputFormat::Hex,
+            InputFormat::Base64,
+            InputFormat::Bits,
+            InputFormat::Binary,
+        ]
+        .into_iter()
+        .find(|format| format.name() == name)
+    }
+}
+
+// Text formats may be wrapped over several lines, so whitespace is ignored
+// when detecting and decoding them. Anything that is not ASCII text is taken
+// to be binary, and text is tried as bits, then hex, then base64, so a
+// transmission written in hex with only the digits 0 and 1 needs the format
+// given explicitly.
+pub fn detect_format(input: &[u8]) -> InputFormat {
+    let text: Vec<u8> = input
+        .iter()
+        .copied()
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if text.is_empty() || !text.iter().all(|b| b.is_ascii_graphic()) {
+        return InputFormat::Binary;
+    }
+
+    if text.iter().all(|b| matches!(b, b'0' | b'1')) {
+        InputFormat::Bits
+    } else if text.iter().all(|b| b.is_ascii_hexdigit()) {
+        InputFormat::Hex
+    } else if text
+        .iter()
+        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'+' | b'/' | b'='))
+    {
+        InputFormat::Base64
+    } else {
+        InputFormat::Binary
+    }
+}
+
+fn input_error(kind: DecodeErrorKind, bit_offset: usize) -> DecodeError {
+    DecodeError {
+        kind,
+        bit_offset,
+        path: Vec::new(),
+    }
+}
+
+fn decode_bits(input: &str) -> Result<BitVec<Msb0, u8>, DecodeError> {
+    input
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .enumerate()
+        .map(|(i, c)| match c {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => Err(input_error(DecodeErrorKind::InvalidBitCharacter(c), i)),
+        })
+        .collect()
+}
+
+fn decode_base64(input: &str) -> Result<Vec<u8>, DecodeError> {
+    let digits: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    base64::engine::general_purpose::STANDARD
+        .decode(&digits)
+        .map_err(|e| match e {
+            base64::DecodeError::InvalidByte(index, byte)
+            | base64::DecodeError::InvalidLastSymbol(index, byte) => input_error(
+                DecodeErrorKind::InvalidBase64Character(byte.into()),
+                index * 6,
+            ),
+            _ => input_error(DecodeErrorKind::InvalidBase64Length, digits.len() * 6),
+        })
+}
+
+// the bits of input read as format, or as the detected format if None
+pub fn decode_input(
+    input: &[u8],
+    format: Option<InputFormat>,
+) -> Result<BitVec<Msb0, u8>, DecodeError> {
+    let format = format.unwrap_or_else(|| detect_format(input));
+    if format == InputFormat::Binary {
+        return Ok(BitVec::from_vec(input.to_vec()));
+    }
+
+    // the offset counts the characters before the bad byte, skipping
+    // whitespace, in the bits each character stands for as the decoders do
+    let text = std::str::from_utf8(input).map_err(|e| {
+        let c = char::from(input[e.valid_up_to()]);
+        let (kind, char_bits) = match format {
+            InputFormat::Hex => (DecodeErrorKind::InvalidHexCharacter(c), 4),
+            InputFormat::Base64 => (DecodeErrorKind::InvalidBase64Character(c), 6),
+            _ => (DecodeErrorKind::InvalidBitCharacter(c), 1),
+        };
+        let valid = std::str::from_utf8(&input[..e.valid_up_to()]).expect("valid up to here");
+        let nchars = valid.chars().filter(|c| !c.is_whitespace()).count();
+        input_error(kind, nchars * char_bits)
+    })?;
+    match format {
+        InputFormat::Hex => decode_hex(text).map(BitVec::from_vec),
+        InputFormat::Base64 => decode_base64(text).map(BitVec::from_vec),
+        InputFormat::Bits => decode_bits(text),
+        InputFormat::Binary => unreachable!(),
+    }
+}
+
+// like decode_packet_from_hex_with, for input in any format
+pub fn decode_packet_from_input_with(
+    input: &[u8],
+    format: Option<InputFormat>,
+    options: &DecodeOptions,
+) -> Result<(Packet, BitVec<Msb0>), DecodeError> {
+    let bits = decode_input(input, format)?;
+    let (packet, remaining_bits) = decode_packet_with(&bits, options)?;
+    if options.strictness == Strictness::Strict {
+        check_padding(&bits, remaining_bits)?;
+    }
+    let remaining_bits_bv = remaining_bits.iter().collect();
+    Ok((packet, remaining_bits_bv))
+}
+
+pub fn decode_packet_from_input(
+    input: &[u8],
+    format: Option<InputFormat>,
+) -> Result<(Packet, BitVec<Msb0>), DecodeError> {
+    decode_packet_from_input_with(input, format, &DecodeOptions::default())
+}
+
+#[test]
+fn test_decode_input_formats() {
+    let expected = Packet::literal(6, 2021u16);
+    let inputs: [(&[u8], InputFormat); 5] = [
+        (b"D2FE28", InputFormat::Hex),
+        (b"d2fe28\n", InputFormat::Hex),
+        (b"0v4o", InputFormat::Base64),
+        (b"110100101111111000101\n", InputFormat::Bits),
+        (&[0xD2, 0xFE, 0x28], InputFormat::Binary),
+    ];
+    for (input, format) in inputs {
+        assert_eq!(detect_format(input), format);
+        let (packet, _remaining_bits) = decode_packet_from_input(input, None).unwrap();
+        assert_eq!(packet, expected);
+    }
+
+    // bit strings need not fill whole bytes
+    let bits = decode_input(b"1101 0010 1111 1110 0010 1", None).unwrap();
+    assert_eq!(bits.len(), 21);
+    let (_packet, remaining_bits) =
+        decode_packet_from_input(b"110100101111111000101", None).unwrap();
+    assert!(remaining_bits.is_empty());
+
+    // hex made only of 0 and 1 has to be asked for
+    let (packet, _remaining_bits) =
+        decode_packet_from_input(b"1010", Some(InputFormat::Hex)).unwrap();
+    assert_eq!(packet.header().unwrap().packet_version, 0);
+    assert_eq!(detect_format(b"1010"), InputFormat::Bits);
+
+    assert_eq!(
+        decode_input(b"0102", Some(InputFormat::Bits))
+            .unwrap_err()
+            .kind,
+        DecodeErrorKind::InvalidBitCharacter('2')
+    );
+    assert_eq!(
+        decode_input(b"0v4", Some(InputFormat::Base64))
+            .unwrap_err()
+            .kind,
+        DecodeErrorKind::InvalidBase64Length
+    );
+    assert_eq!(
+        decode_input(b"D2 FE\xFF28", Some(InputFormat::Hex)).unwrap_err(),
+        DecodeError {
+            kind: DecodeErrorKind::InvalidHexCharacter('\u{FF}'),
+            bit_offset: 16,
+            path: Vec::new(),
+        }
+    );
+    assert_eq!(
+        decode_input(b"0v!o", Some(InputFormat::Base64)).unwrap_err(),
+        DecodeError {
+            kind: DecodeErrorKind::InvalidBase64Character('!'),
+            bit_offset: 12,
+            path: Vec::new(),
+        }
+    );
+}
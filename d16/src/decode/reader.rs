@@ -0,0 +1,182 @@
+use crate::decode::stream::PacketStream;
+use crate::decode::*;
+use std::collections::VecDeque;
+use std::io::{BufReader, Read};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderFormat {
+    Hex,
+    Raw,
+}
+
+// Pulls bits from a reader only as far as the decoder looks ahead, which is at
+// most one total length field's worth of bits. Lookahead is kept as a count of
+// leading zero bits followed by the buffered bits from the first one bit on,
+// so long runs of zero padding cost nothing to skip over.
+pub struct ReaderSource<R: Read> {
+    reader: BufReader<R>,
+    format: ReaderFormat,
+    position: usize,
+    zeros: usize,
+    buffer: VecDeque<bool>,
+    eof: bool,
+    // whether an odd number of hex digits has been read so far
+    odd_digits: bool,
+}
+
+impl<R: Read> ReaderSource<R> {
+    pub fn new(reader: R, format: ReaderFormat) -> ReaderSource<R> {
+        ReaderSource {
+            reader: BufReader::new(reader),
+            format,
+            position: 0,
+            zeros: 0,
+            buffer: VecDeque::new(),
+            eof: false,
+            odd_digits: false,
+        }
+    }
+
+    fn buffered(&self) -> usize {
+        self.zeros + self.buffer.len()
+    }
+
+    fn push_bits(&mut self, value: u8, nbits: usize) {
+        for i in (0..nbits).rev() {
+            let bit = (value >> i) & 1 == 1;
+            if !bit && self.buffer.is_empty() {
+                self.zeros += 1;
+            } else {
+                self.buffer.push_back(bit);
+            }
+        }
+    }
+
+    fn error(&self, kind: DecodeErrorKind) -> DecodeError {
+        DecodeError {
+            kind,
+            bit_offset: self.position + self.buffered(),
+            path: Vec::new(),
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<Option<u8>, DecodeError> {
+        let mut byte = [0u8];
+        loop {
+            match self.reader.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(self.error(DecodeErrorKind::Io(e.kind()))),
+            }
+        }
+    }
+
+    fn read_more(&mut self) -> Result<(), DecodeError> {
+        let byte = match self.read_byte()? {
+            Some(byte) => byte,
+            None => {
+                self.eof = true;
+                // hex digits come in pairs, as decode_hex insists
+                if self.odd_digits {
+                    return Err(self.error(DecodeErrorKind::OddHexLength));
+                }
+                return Ok(());
+            }
+        };
+
+        match self.format {
+            ReaderFormat::Raw => self.push_bits(byte, 8),
+            ReaderFormat::Hex => {
+                if byte.is_ascii_whitespace() {
+                    return Ok(());
+                }
+                match (byte as char).to_digit(16) {
+                    Some(nibble) => {
+                        self.push_bits(nibble as u8, 4);
+                        self.odd_digits = !self.odd_digits;
+                    }
+                    None => {
+                        let kind = DecodeErrorKind::InvalidHexCharacter(byte as char);
+                        return Err(self.error(kind));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> BitSource for ReaderSource<R> {
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn available(&mut self, nbits: usize) -> Result<usize, DecodeError> {
+        while self.buffered() < nbits && !self.eof {
+            self.read_more()?;
+        }
+        Ok(nbits.min(self.buffered()))
+    }
+
+    fn take_bit(&mut self) -> bool {
+        self.position += 1;
+        if self.zeros > 0 {
+            self.zeros -= 1;
+            false
+        } else {
+            self.buffer
+                .pop_front()
+                .expect("bit taken before it was available")
+        }
+    }
+
+    fn only_zeros_remain(&mut self) -> Result<bool, DecodeError> {
+        // the buffer always starts with a one bit when it is not empty
+        while self.buffer.is_empty() && !self.eof {
+            self.read_more()?;
+        }
+        Ok(self.buffer.is_empty())
+    }
+}
+
+pub fn read_packets<R: Read>(reader: R, format: ReaderFormat) -> PacketStream<ReaderSource<R>> {
+    read_packets_with(reader, format, DecodeOptions::default())
+}
+
+pub fn read_packets_with<R: Read>(
+    reader: R,
+    format: ReaderFormat,
+    options: DecodeOptions,
+) -> PacketStream<ReaderSource<R>> {
+    PacketStream::from_source(ReaderSource::new(reader, format), options)
+}
+
+#[test]
+fn test_read_packets() {
+    let input = "D2FE28\n38006F4529\n1200EE00D40C823060\n0000\n";
+    let expected = stream::decode_packets_from_hex(input).unwrap();
+
+    let packets: Result<Vec<_>, _> = read_packets(input.as_bytes(), ReaderFormat::Hex).collect();
+    assert_eq!(packets.unwrap(), expected);
+
+    let raw = decode_hex(input).unwrap();
+    let packets: Result<Vec<_>, _> = read_packets(&raw[..], ReaderFormat::Raw).collect();
+    assert_eq!(packets.unwrap(), expected);
+
+    let mut packets = read_packets("D2FE28\n38006F4X".as_bytes(), ReaderFormat::Hex);
+    assert!(packets.next().unwrap().is_ok());
+    let error = packets.next().unwrap().unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::InvalidHexCharacter('X'));
+    assert_eq!(error.bit_offset, 52);
+    assert!(packets.next().is_none());
+
+    // a trailing half byte is rejected by both
+    let expected = stream::decode_packets_from_hex("D2FE2").unwrap_err();
+    let error = read_packets("D2FE2".as_bytes(), ReaderFormat::Hex)
+        .next()
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(error, expected);
+    assert_eq!(error.kind, DecodeErrorKind::OddHexLength);
+}
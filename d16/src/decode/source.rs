@@ -0,0 +1,52 @@
+use crate::decode::DecodeError;
+use crate::packet::*;
+
+pub trait BitSource {
+    // number of bits read so far
+    fn position(&self) -> usize;
+
+    // looks up to nbits ahead and returns how many of them can be read, which
+    // is fewer than nbits only at the end of the input
+    fn available(&mut self, nbits: usize) -> Result<usize, DecodeError>;
+
+    // reads the next bit, only valid once available has reported it
+    fn take_bit(&mut self) -> bool;
+
+    // true if nothing but zero bits are left before the end of the input
+    fn only_zeros_remain(&mut self) -> Result<bool, DecodeError>;
+}
+
+pub struct SliceSource<'a> {
+    bits: &'a D16BitSlice,
+    position: usize,
+}
+
+impl<'a> SliceSource<'a> {
+    pub fn new(bits: &'a D16BitSlice) -> SliceSource<'a> {
+        SliceSource { bits, position: 0 }
+    }
+
+    pub fn remaining_bits(&self) -> &'a D16BitSlice {
+        &self.bits[self.position..]
+    }
+}
+
+impl<'a> BitSource for SliceSource<'a> {
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn available(&mut self, nbits: usize) -> Result<usize, DecodeError> {
+        Ok(nbits.min(self.bits.len() - self.position))
+    }
+
+    fn take_bit(&mut self) -> bool {
+        let bit = self.bits[self.position];
+        self.position += 1;
+        bit
+    }
+
+    fn only_zeros_remain(&mut self) -> Result<bool, DecodeError> {
+        Ok(self.remaining_bits().not_any())
+    }
+}
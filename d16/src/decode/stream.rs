@@ -0,0 +1,147 @@
+use crate::decode::*;
+
+// start and end are bit offsets from the start of the stream, end exclusive,
+// as are the offsets of the packets inside
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamedPacket {
+    pub packet: Packet,
+    pub offsets: PacketOffsets,
+    pub start: usize,
+    pub end: usize,
+}
+
+// Every packet in a capture is padded with zeros to a byte boundary, see
+// PADDING_ALIGNMENT, and the padding is skipped before decoding the next one.
+// Only the padding up to that boundary is skipped: a whole zero byte is the
+// start of a version 0 sum, so zero bytes between packets are read as one.
+// The stream ends once only zero bits are left, and stops after the first
+// error since there is no telling where the next packet starts.
+pub struct PacketStream<S: BitSource> {
+    source: S,
+    options: DecodeOptions,
+    done: bool,
+}
+
+impl<'a> PacketStream<SliceSource<'a>> {
+    pub fn new(bits: &'a D16BitSlice) -> PacketStream<SliceSource<'a>> {
+        PacketStream::with_options(bits, DecodeOptions::default())
+    }
+
+    pub fn with_options(
+        bits: &'a D16BitSlice,
+        options: DecodeOptions,
+    ) -> PacketStream<SliceSource<'a>> {
+        PacketStream::from_source(SliceSource::new(bits), options)
+    }
+}
+
+impl<S: BitSource> PacketStream<S> {
+    pub fn from_source(source: S, options: DecodeOptions) -> PacketStream<S> {
+        PacketStream {
+            source,
+            options,
+            done: false,
+        }
+    }
+
+    fn skip_padding(&mut self) -> Result<(), DecodeError> {
+        let start = self.source.position();
+        let padding = (PADDING_ALIGNMENT - start % PADDING_ALIGNMENT) % PADDING_ALIGNMENT;
+        let padding = self.source.available(padding)?;
+        let nonzero_padding = (0..padding).fold(false, |acc, _| self.source.take_bit() || acc);
+
+        if self.options.strictness == Strictness::Strict && nonzero_padding {
+            return Err(DecodeError {
+                kind: DecodeErrorKind::NonZeroPadding,
+                bit_offset: start,
+                path: Vec::new(),
+            });
+        }
+        Ok(())
+    }
+
+    fn decode_next(&mut self) -> Result<StreamedPacket, DecodeError> {
+        let start = self.source.position();
+        let (packet, offsets) = decode_offsets_from_source(&mut self.source, &self.options, false)?;
+        let end = self.source.position();
+        self.skip_padding()?;
+        Ok(StreamedPacket {
+            packet,
+            offsets,
+            start,
+            end,
+        })
+    }
+}
+
+impl<S: BitSource> Iterator for PacketStream<S> {
+    type Item = Result<StreamedPacket, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let result = match self.source.only_zeros_remain() {
+            Ok(true) => {
+                self.done = true;
+                return None;
+            }
+            Ok(false) => self.decode_next(),
+            Err(e) => Err(e),
+        };
+
+        if result.is_err() {
+            self.done = true;
+        }
+        Some(result)
+    }
+}
+
+pub fn decode_packets_from_hex(input: &str) -> Result<Vec<StreamedPacket>, DecodeError> {
+    decode_packets_from_hex_with(input, DecodeOptions::default())
+}
+
+pub fn decode_packets_from_hex_with(
+    input: &str,
+    options: DecodeOptions,
+) -> Result<Vec<StreamedPacket>, DecodeError> {
+    let decoded = decode_hex(input)?;
+    PacketStream::with_options(decoded.view_bits::<Msb0>(), options).collect()
+}
+
+#[test]
+fn test_packet_stream() {
+    let input = "D2FE28\n38006F4529\n1200EE00D40C823060\n0000";
+    let packets = decode_packets_from_hex(input).unwrap();
+    let ranges: Vec<(usize, usize)> = packets.iter().map(|p| (p.start, p.end)).collect();
+    assert_eq!(ranges, vec![(0, 21), (24, 73), (80, 131)]);
+
+    for streamed_packet in &packets {
+        let hex = crate::encode::encode_packet_to_hex(&streamed_packet.packet).unwrap();
+        assert!(input.replace('\n', "").contains(&hex));
+    }
+
+    let strict = DecodeOptions::strict();
+    let error = decode_packets_from_hex_with("D2FE2938006F45291200", strict).unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::NonZeroPadding);
+    assert_eq!(error.bit_offset, 21);
+
+    // zero bytes after the last packet are skipped, but between packets they
+    // start a sum whose total length runs past the end
+    let packets = decode_packets_from_hex("D2FE2800000000").unwrap();
+    assert_eq!(packets.len(), 1);
+    let error = decode_packets_from_hex("D2FE2800D2FE28").unwrap_err();
+    assert_eq!(
+        error.kind,
+        DecodeErrorKind::LengthOverrun {
+            declared: 13503,
+            available: 10
+        }
+    );
+    assert_eq!(error.bit_offset, 31);
+
+    let error = decode_packets_from_hex("D2FE28D2FE").unwrap_err();
+    assert_eq!(error.kind, DecodeErrorKind::UnterminatedLiteral);
+    assert_eq!(error.bit_offset, 30);
+}
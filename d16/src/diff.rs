@@ -0,0 +1,358 @@
+use crate::eval::{compute_packet_result, EvalError};
+use crate::packet::*;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    VersionChanged { old: u8, new: u8 },
+    TypeChanged { old: u8, new: u8 },
+    LiteralChanged { old: Literal, new: Literal },
+}
+
+// path is in the old tree for removed packets and in the new tree for
+// everything else
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub path: Vec<usize>,
+    pub kind: ChangeKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketDiff {
+    pub changes: Vec<Change>,
+    pub old_result: Result<Literal, EvalError>,
+    pub new_result: Result<Literal, EvalError>,
+}
+
+impl PacketDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn result_changed(&self) -> bool {
+        self.old_result != self.new_result
+    }
+}
+
+fn type_name(packet_type: u8) -> String {
+    let packet_header = PacketHeader {
+        packet_version: 0,
+        packet_type,
+    };
+    packet_header.type_name()
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = format_path(&self.path);
+        match &self.kind {
+            ChangeKind::Added => write!(f, "added {}", path),
+            ChangeKind::Removed => write!(f, "removed {}", path),
+            ChangeKind::VersionChanged { old, new } => {
+                write!(f, "version {}: {} -> {}", path, old, new)
+            }
+            ChangeKind::TypeChanged { old, new } => write!(
+                f,
+                "type {}: {} -> {}",
+                path,
+                type_name(*old),
+                type_name(*new)
+            ),
+            ChangeKind::LiteralChanged { old, new } => {
+                write!(f, "literal {}: {} -> {}", path, old, new)
+            }
+        }
+    }
+}
+
+impl fmt::Display for PacketDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        let show = |result: &Result<Literal, EvalError>| match result {
+            Ok(value) => value.to_string(),
+            Err(e) => format!("error ({})", e),
+        };
+        if self.result_changed() {
+            writeln!(
+                f,
+                "result changed: {} -> {}",
+                show(&self.old_result),
+                show(&self.new_result)
+            )
+        } else {
+            writeln!(f, "result unchanged: {}", show(&self.new_result))
+        }
+    }
+}
+
+enum Work<'a> {
+    Compare {
+        old: &'a Packet,
+        new: &'a Packet,
+        old_path: Vec<usize>,
+        new_path: Vec<usize>,
+    },
+    Record(Change),
+}
+
+// pairs up subpackets for which same holds by longest common subsequence,
+// returning the matched (old, new) indices in order
+fn match_subpackets(
+    old: &[Packet],
+    new: &[Packet],
+    same: fn(&Packet, &Packet) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if same(&old[i], &new[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut matches = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if same(&old[i], &new[j]) {
+            matches.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    matches
+}
+
+struct Aligner<'a, 'p> {
+    old: &'a [Packet],
+    new: &'a [Packet],
+    old_path: &'p [usize],
+    new_path: &'p [usize],
+    work: Vec<Work<'a>>,
+}
+
+impl<'a, 'p> Aligner<'a, 'p> {
+    fn child_path(path: &[usize], i: usize) -> Vec<usize> {
+        let mut path = path.to_vec();
+        path.push(i);
+        path
+    }
+
+    fn compare(&mut self, i: usize, j: usize) {
+        self.work.push(Work::Compare {
+            old: &self.old[i],
+            new: &self.new[j],
+            old_path: Aligner::child_path(self.old_path, i),
+            new_path: Aligner::child_path(self.new_path, j),
+        });
+    }
+
+    // Equal subpackets are matched first, then subpackets of the same type
+    // between them. Whatever is still unmatched is compared pairwise in order
+    // and the leftovers on either side were removed or added.
+    fn align(&mut self, old: Range<usize>, new: Range<usize>, pass: usize) {
+        let same: fn(&Packet, &Packet) -> bool = match pass {
+            0 => |a, b| a == b,
+            1 => |a, b| a.header().map(|h| h.packet_type) == b.header().map(|h| h.packet_type),
+            _ => {
+                let pairs = old.len().min(new.len());
+                for k in 0..pairs {
+                    self.compare(old.start + k, new.start + k);
+                }
+                for i in old.start + pairs..old.end {
+                    self.work.push(Work::Record(Change {
+                        path: Aligner::child_path(self.old_path, i),
+                        kind: ChangeKind::Removed,
+                    }));
+                }
+                for j in new.start + pairs..new.end {
+                    self.work.push(Work::Record(Change {
+                        path: Aligner::child_path(self.new_path, j),
+                        kind: ChangeKind::Added,
+                    }));
+                }
+                return;
+            }
+        };
+
+        let mut matches = match_subpackets(&self.old[old.clone()], &self.new[new.clone()], same);
+        matches.push((old.len(), new.len()));
+        let (mut i, mut j) = (old.start, new.start);
+        for (match_i, match_j) in matches {
+            let (match_i, match_j) = (old.start + match_i, new.start + match_j);
+            self.align(i..match_i, j..match_j, pass + 1);
+            // equal subpackets need no work, the final entry is a sentinel
+            if pass > 0 && match_i < old.end {
+                self.compare(match_i, match_j);
+            }
+            i = match_i + 1;
+            j = match_j + 1;
+        }
+    }
+}
+
+fn subpacket_work<'a>(
+    old: &'a [Packet],
+    new: &'a [Packet],
+    old_path: &[usize],
+    new_path: &[usize],
+) -> Vec<Work<'a>> {
+    let mut aligner = Aligner {
+        old,
+        new,
+        old_path,
+        new_path,
+        work: Vec::new(),
+    };
+    aligner.align(0..old.len(), 0..new.len(), 0);
+    aligner.work
+}
+
+fn compare<'a>(
+    old: &'a Packet,
+    new: &'a Packet,
+    old_path: Vec<usize>,
+    new_path: Vec<usize>,
+    changes: &mut Vec<Change>,
+) -> Vec<Work<'a>> {
+    // an invalid packet has nothing to compare, so it is reported as
+    // replacing whatever was there
+    let (old_header, new_header) = match (old.header(), new.header()) {
+        (Some(old_header), Some(new_header)) => (old_header, new_header),
+        _ => {
+            changes.push(Change {
+                path: old_path,
+                kind: ChangeKind::Removed,
+            });
+            changes.push(Change {
+                path: new_path,
+                kind: ChangeKind::Added,
+            });
+            return Vec::new();
+        }
+    };
+    if old_header.packet_version != new_header.packet_version {
+        changes.push(Change {
+            path: new_path.clone(),
+            kind: ChangeKind::VersionChanged {
+                old: old_header.packet_version,
+                new: new_header.packet_version,
+            },
+        });
+    }
+    if old_header.packet_type != new_header.packet_type {
+        changes.push(Change {
+            path: new_path.clone(),
+            kind: ChangeKind::TypeChanged {
+                old: old_header.packet_type,
+                new: new_header.packet_type,
+            },
+        });
+    }
+
+    match (old, new) {
+        (Packet::Literal(_, old_literal, _), Packet::Literal(_, new_literal, _)) => {
+            if old_literal != new_literal {
+                changes.push(Change {
+                    path: new_path,
+                    kind: ChangeKind::LiteralChanged {
+                        old: old_literal.to_owned(),
+                        new: new_literal.to_owned(),
+                    },
+                });
+            }
+            Vec::new()
+        }
+        _ => subpacket_work(old.subpackets(), new.subpackets(), &old_path, &new_path),
+    }
+}
+
+pub fn diff_packets(old: &Packet, new: &Packet) -> PacketDiff {
+    let mut changes = Vec::new();
+    let mut stack = vec![Work::Compare {
+        old,
+        new,
+        old_path: Vec::new(),
+        new_path: Vec::new(),
+    }];
+    while let Some(work) = stack.pop() {
+        match work {
+            Work::Compare {
+                old,
+                new,
+                old_path,
+                new_path,
+            } => {
+                if old == new {
+                    continue;
+                }
+                let work = compare(old, new, old_path, new_path, &mut changes);
+                stack.extend(work.into_iter().rev());
+            }
+            Work::Record(change) => changes.push(change),
+        }
+    }
+
+    PacketDiff {
+        changes,
+        old_result: compute_packet_result(old),
+        new_result: compute_packet_result(new),
+    }
+}
+
+#[cfg(test)]
+use crate::compile::{compile_expression, CompileOptions};
+
+#[test]
+fn test_diff_packets() {
+    let compile = |input| compile_expression(input, &CompileOptions::default()).unwrap();
+
+    let old = compile("1 + (2 * 3) + min(4, 5)");
+    assert!(diff_packets(&old, &old).is_empty());
+
+    let new = compile("1 + 7 + (2 * 3)@v2 + min(4, 6)");
+    let diff = diff_packets(&old, &new);
+    let changes: Vec<String> = diff.changes.iter().map(Change::to_string).collect();
+    assert_eq!(
+        changes,
+        ["added /1", "version /2: 0 -> 2", "literal /3/1: 5 -> 6"]
+    );
+    assert!(diff.result_changed());
+
+    let diff = diff_packets(&old, &compile("1 + (2 * 3) + max(4, 5)"));
+    assert_eq!(
+        diff.to_string(),
+        "type /2: minimum -> maximum\nresult changed: 11 -> 12\n"
+    );
+    let diff = diff_packets(&compile("min(4, 4)"), &compile("max(4, 4)"));
+    assert!(!diff.result_changed());
+    assert_eq!(
+        diff.to_string(),
+        "type /: minimum -> maximum\nresult unchanged: 4\n"
+    );
+
+    let new = compile("1 + 9");
+    let diff = diff_packets(&old, &new);
+    let changes: Vec<String> = diff.changes.iter().map(Change::to_string).collect();
+    assert_eq!(
+        changes,
+        [
+            "type /1: product -> literal",
+            "removed /1/0",
+            "removed /1/1",
+            "removed /2",
+        ]
+    );
+    assert_eq!(diff.new_result, Ok(Literal::from(10u8)));
+}
@@ -0,0 +1,168 @@
+use crate::decode::*;
+use crate::packet::*;
+use bitvec::prelude::*;
+use std::fmt;
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = format!("{}..{}", self.bit_range.start, self.bit_range.end);
+        let indent = "  ".repeat(self.depth);
+        let label = format!("{}{}", indent, self.kind.name());
+        write!(f, "{:>11}  {:<32} {}", range, label, self.raw)?;
+
+        match self.kind {
+            FieldKind::TypeId => {
+                let packet_header = PacketHeader {
+                    packet_version: 0,
+                    packet_type: self.value as u8,
+                };
+                write!(f, " = {} ({})", self.value, packet_header.type_name())
+            }
+            FieldKind::LiteralContinuation => {
+                let last = if self.value == 0 { " (last group)" } else { "" };
+                write!(f, "{}", last)
+            }
+            FieldKind::LengthTypeId => {
+                let mode = match self.value {
+                    0 => TOTAL_LENGTH_IN_BITS_SIZE,
+                    _ => NUMBER_OF_SUBPACKETS_SIZE,
+                };
+                write!(f, " = {} ({}-bit length)", self.value, mode)
+            }
+            FieldKind::LengthSlack | FieldKind::Padding => Ok(()),
+            _ => write!(f, " = {}", self.value),
+        }
+    }
+}
+
+pub struct Disassembly {
+    pub fields: Vec<Field>,
+    pub error: Option<DecodeError>,
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for field in &self.fields {
+            writeln!(f, "{}", field)?;
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// Lists every field of the outermost packet and the padding after it. When
+// decoding fails the fields read up to that point are still listed, followed
+// by the error. Strict disassembly checks the padding as strict decoding does.
+pub fn disassemble_with(bits: &D16BitSlice, options: &DecodeOptions) -> Disassembly {
+    let mut source = SliceSource::new(bits);
+    let (mut result, mut fields) = decode_fields_from_source(&mut source, options);
+
+    let remaining_bits = source.remaining_bits();
+    if result.is_ok() && !remaining_bits.is_empty() {
+        let start = source.position();
+        fields.push(Field {
+            kind: FieldKind::Padding,
+            bit_range: start..bits.len(),
+            depth: 0,
+            raw: remaining_bits
+                .iter()
+                .map(|bit| if *bit { '1' } else { '0' })
+                .collect(),
+            value: 0,
+        });
+    }
+    if result.is_ok() && options.strictness == Strictness::Strict {
+        if let Err(e) = check_padding(bits, remaining_bits) {
+            result = Err(e);
+        }
+    }
+
+    Disassembly {
+        fields,
+        error: result.err(),
+    }
+}
+
+pub fn disassemble(bits: &D16BitSlice) -> Disassembly {
+    disassemble_with(bits, &DecodeOptions::default())
+}
+
+pub fn disassemble_hex(input: &str) -> Result<Disassembly, DecodeError> {
+    let decoded = decode_hex(input)?;
+    Ok(disassemble(decoded.view_bits::<Msb0>()))
+}
+
+#[test]
+fn test_disassemble_literal() {
+    let disassembly = disassemble_hex("D2FE28").unwrap();
+    let expected = [
+        "       0..3  version                          110 = 6",
+        "       3..6  type ID                          100 = 4 (literal)",
+        "       6..7  continuation                     1",
+        "      7..11  literal group                    0111 = 7",
+        "     11..12  continuation                     1",
+        "     12..16  literal group                    1110 = 14",
+        "     16..17  continuation                     0 (last group)",
+        "     17..21  literal group                    0101 = 5",
+        "     21..24  padding                          000",
+    ];
+    assert_eq!(
+        disassembly.to_string().lines().collect::<Vec<_>>(),
+        expected
+    );
+}
+
+#[test]
+fn test_disassemble_operator() {
+    let disassembly = disassemble_hex("EE00D40C823060").unwrap();
+    let lines: Vec<String> = disassembly.to_string().lines().map(String::from).collect();
+    assert_eq!(
+        lines[2],
+        "       6..7  length type ID                   1 = 1 (11-bit length)"
+    );
+    assert_eq!(
+        lines[3],
+        "      7..18  subpacket count                  00000000011 = 3"
+    );
+    assert_eq!(
+        lines[4],
+        "     18..21    version                        010 = 2"
+    );
+
+    // the fields tile the whole transmission
+    let mut end = 0;
+    for field in &disassembly.fields {
+        assert_eq!(field.bit_range.start, end);
+        end = field.bit_range.end;
+    }
+    assert_eq!(end, 56);
+
+    // subpackets that fill a total length exactly leave no slack
+    let disassembly = disassemble_hex("38006F45291200").unwrap();
+    let kinds: Vec<FieldKind> = disassembly.fields.iter().map(|field| field.kind).collect();
+    assert!(!kinds.contains(&FieldKind::LengthSlack));
+    assert_eq!(kinds.last(), Some(&FieldKind::Padding));
+    assert!(disassembly
+        .fields
+        .iter()
+        .all(|field| !field.bit_range.is_empty()));
+
+    let disassembly = disassemble_hex("EE00D40C8230").unwrap();
+    assert!(disassembly.error.is_some());
+    assert_eq!(disassembly.fields.last().unwrap().bit_range, 43..46);
+}
+
+#[test]
+fn test_disassemble_strict_padding() {
+    let decoded = decode_hex("D2FE29").unwrap();
+    let bits = decoded.view_bits::<Msb0>();
+    assert!(disassemble(bits).error.is_none());
+
+    let disassembly = disassemble_with(bits, &DecodeOptions::strict());
+    assert_eq!(disassembly.fields.last().unwrap().kind, FieldKind::Padding);
+    let error = disassembly.error.unwrap();
+    assert_eq!(error.kind, DecodeErrorKind::NonZeroPadding);
+    assert_eq!(error.bit_offset, 21);
+}
@@ -0,0 +1,139 @@
+use crate::eval::compute_packet_result;
+use crate::packet::*;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+
+fn node_id(path: &[usize]) -> String {
+    let mut id = "p".to_string();
+    for i in path {
+        id.push_str(&format!("_{}", i));
+    }
+    id
+}
+
+struct Dot {
+    lines: Vec<String>,
+}
+
+impl PacketFold for Dot {
+    // the packet's value, if it and all of its subpackets evaluate
+    type Output = Option<Literal>;
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        context: &VisitContext,
+        subpacket_values: Vec<Option<Literal>>,
+    ) -> Option<Literal> {
+        let id = node_id(context.path);
+
+        // evaluate the node alone, with its subpackets replaced by the
+        // literals they evaluated to
+        let (value, detail) = match packet {
+            Packet::Literal(_packet_header, literal, _ngroups) => {
+                (Some(literal.to_owned()), literal.to_string())
+            }
+            Packet::Operator(packet_header, length, _) => {
+                match subpacket_values
+                    .into_iter()
+                    .collect::<Option<Vec<Literal>>>()
+                {
+                    Some(values) => {
+                        let literals = values
+                            .into_iter()
+                            .map(|value| Packet::literal(0, value))
+                            .collect();
+                        let node = Packet::Operator(*packet_header, *length, literals);
+                        match compute_packet_result(&node) {
+                            Ok(value) => {
+                                let detail = format!("= {}", value);
+                                (Some(value), detail)
+                            }
+                            Err(e) => (None, format!("error: {}", e.kind)),
+                        }
+                    }
+                    None => (None, "(no value)".to_string()),
+                }
+            }
+            Packet::Invalid { reason, .. } => (None, reason.to_string()),
+        };
+
+        let title = match packet {
+            Packet::Invalid { bit_range, .. } => {
+                format!("invalid bits {}..{}", bit_range.start, bit_range.end)
+            }
+            _ => {
+                let packet_version = context.header.map_or(0, |h| h.packet_version);
+                format!("v{} {}", packet_version, packet.type_name())
+            }
+        };
+        self.lines
+            .push(format!("  {} [label=\"{}\\n{}\"];", id, title, detail));
+        let mut path = context.path.to_vec();
+        for i in 0..packet.subpackets().len() {
+            path.push(i);
+            self.lines
+                .push(format!("  {} -> {} [label=\"{}\"];", id, node_id(&path), i));
+            path.pop();
+        }
+        value
+    }
+}
+
+// Renders packet as a Graphviz digraph with one box per packet showing its
+// version, type and value. Subpackets are laid out left to right in order.
+pub fn to_dot(packet: &Packet) -> String {
+    let mut dot = Dot { lines: Vec::new() };
+    fold_iterative(packet, &mut dot);
+
+    let mut out = String::from("digraph packets {\n  ordering=out;\n  node [shape=box];\n");
+    for line in dot.lines {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+
+#[test]
+fn test_to_dot() {
+    let (packet, _remaining_bits) = decode_packet_from_hex("9C0141080250320F1802104A08").unwrap();
+    let expected = r#"digraph packets {
+  ordering=out;
+  node [shape=box];
+  p_0_0 [label="v2 literal\n1"];
+  p_0_1 [label="v4 literal\n3"];
+  p_0 [label="v2 sum\n= 4"];
+  p_0 -> p_0_0 [label="0"];
+  p_0 -> p_0_1 [label="1"];
+  p_1_0 [label="v0 literal\n2"];
+  p_1_1 [label="v2 literal\n2"];
+  p_1 [label="v6 product\n= 4"];
+  p_1 -> p_1_0 [label="0"];
+  p_1 -> p_1_1 [label="1"];
+  p [label="v4 equal_to\n= 1"];
+  p -> p_0 [label="0"];
+  p -> p_1 [label="1"];
+}
+"#;
+    assert_eq!(to_dot(&packet), expected);
+
+    let packet = Packet::operator(
+        0,
+        PacketOperatorType::Sum,
+        LengthMode::NumberOfSubpackets,
+        vec![Packet::operator(
+            1,
+            PacketOperatorType::LessThan,
+            LengthMode::NumberOfSubpackets,
+            vec![Packet::literal(2, 1u8)],
+        )],
+    );
+    let dot = to_dot(&packet);
+    assert!(
+        dot.contains("p_0 [label=\"v1 less_than\\nerror: takes exactly 2 subpackets but has 1\"];")
+    );
+    assert!(dot.contains("p [label=\"v0 sum\\n(no value)\"];"));
+}
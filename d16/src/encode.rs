@@ -0,0 +1,632 @@
+use crate::packet::*;
+use bitvec::prelude::*;
+use num_traits::FromPrimitive;
+use std::error::Error;
+use std::fmt;
+
+// Preserve writes the length field recorded in each operator, Force rewrites
+// every operator to use the given mode and Smallest picks whichever mode
+// takes fewer bits for each operator. Smallest also drops the leading zero
+// groups that literals were sent with, the others keep them. Packets do not
+// keep the slack bits a lenient decode skipped at the end of a total length
+// operator, so Preserve writes the slack back as zeros and only reproduces
+// the original bits exactly when the slack was all zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthStrategy {
+    Preserve,
+    Force(LengthMode),
+    Smallest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncodeErrorKind {
+    VersionOutOfRange(u8),
+    TypeOutOfRange(u8),
+    MismatchedPacketType(u8),
+    TotalLengthOverflow(usize),
+    SubpacketCountOverflow(usize),
+    DeclaredLengthMismatch {
+        declared: OperatorLength,
+        actual: usize,
+    },
+    InvalidPacket,
+    TooFewLiteralGroups {
+        ngroups: usize,
+        needed: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodeError {
+    pub kind: EncodeErrorKind,
+    pub path: Vec<usize>,
+}
+
+impl fmt::Display for EncodeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeErrorKind::VersionOutOfRange(version) => {
+                write!(f, "packet version {} does not fit in 3 bits", version)
+            }
+            EncodeErrorKind::TypeOutOfRange(packet_type) => {
+                write!(f, "packet type {} does not fit in 3 bits", packet_type)
+            }
+            EncodeErrorKind::MismatchedPacketType(packet_type) => {
+                write!(
+                    f,
+                    "packet type {} does not match packet contents",
+                    packet_type
+                )
+            }
+            EncodeErrorKind::TotalLengthOverflow(length) => write!(
+                f,
+                "subpackets take {} bits, more than a {}-bit length field can hold",
+                length, TOTAL_LENGTH_IN_BITS_SIZE
+            ),
+            EncodeErrorKind::SubpacketCountOverflow(count) => write!(
+                f,
+                "{} subpackets are more than a {}-bit count field can hold",
+                count, NUMBER_OF_SUBPACKETS_SIZE
+            ),
+            EncodeErrorKind::DeclaredLengthMismatch { declared, actual } => match declared {
+                OperatorLength::TotalLengthInBits(total_length) => write!(
+                    f,
+                    "length field declares {} bits but subpackets take {}",
+                    total_length, actual
+                ),
+                OperatorLength::NumberOfSubpackets(nsubpackets) => write!(
+                    f,
+                    "length field declares {} subpackets but there are {}",
+                    nsubpackets, actual
+                ),
+            },
+            EncodeErrorKind::InvalidPacket => {
+                write!(f, "invalid packets have no bits to encode")
+            }
+            EncodeErrorKind::TooFewLiteralGroups { ngroups, needed } => write!(
+                f,
+                "literal needs {} groups but is to be sent with {}",
+                needed, ngroups
+            ),
+        }
+    }
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (path {})", self.kind, format_path(&self.path))
+    }
+}
+
+impl Error for EncodeError {}
+
+fn push_bits(out: &mut BitVec<Msb0, u8>, value: usize, width: usize) {
+    for i in (0..width).rev() {
+        out.push((value >> i) & 1 == 1);
+    }
+}
+
+// an operator whose subpackets are being written, its total length is filled
+// in once they all are
+struct OpenOperator<'p> {
+    length: &'p OperatorLength,
+    subpackets: &'p [Packet],
+    mode: LengthMode,
+    length_start: usize,
+    subpackets_start: usize,
+    next_subpacket: usize,
+}
+
+struct Encoder {
+    strategy: LengthStrategy,
+    path: Vec<usize>,
+    out: BitVec<Msb0, u8>,
+}
+
+impl Encoder {
+    fn error(&self, kind: EncodeErrorKind) -> EncodeError {
+        EncodeError {
+            kind,
+            path: self.path.clone(),
+        }
+    }
+
+    fn encode_header(&mut self, packet_header: &PacketHeader) -> Result<(), EncodeError> {
+        if packet_header.packet_version >= 1 << PACKET_VERSION_SIZE {
+            let kind = EncodeErrorKind::VersionOutOfRange(packet_header.packet_version);
+            return Err(self.error(kind));
+        }
+        if packet_header.packet_type >= 1 << PACKET_TYPE_SIZE {
+            let kind = EncodeErrorKind::TypeOutOfRange(packet_header.packet_type);
+            return Err(self.error(kind));
+        }
+        push_bits(
+            &mut self.out,
+            packet_header.packet_version.into(),
+            PACKET_VERSION_SIZE,
+        );
+        push_bits(
+            &mut self.out,
+            packet_header.packet_type.into(),
+            PACKET_TYPE_SIZE,
+        );
+        Ok(())
+    }
+
+    fn encode_literal(&mut self, literal: &Literal, ngroups: usize) -> Result<(), EncodeError> {
+        let needed = literal_groups(literal);
+        let ngroups = match self.strategy {
+            LengthStrategy::Smallest => needed,
+            LengthStrategy::Preserve | LengthStrategy::Force(_) => ngroups,
+        };
+        if ngroups < needed {
+            let kind = EncodeErrorKind::TooFewLiteralGroups { ngroups, needed };
+            return Err(self.error(kind));
+        }
+        for i in (0..ngroups).rev() {
+            self.out.push(i != 0);
+            let group = literal_group(literal, i);
+            push_bits(&mut self.out, group.into(), LITERAL_GROUP_SIZE);
+        }
+        Ok(())
+    }
+
+    // writes everything up to the subpackets, with a total length left as
+    // zeros until finish_operator knows it
+    fn open_operator<'p>(
+        &mut self,
+        length: &'p OperatorLength,
+        subpackets: &'p [Packet],
+    ) -> Result<OpenOperator<'p>, EncodeError> {
+        let mode = match self.strategy {
+            LengthStrategy::Preserve => length.mode(),
+            LengthStrategy::Force(mode) => mode,
+            // both modes are followed by the same subpackets, so the shorter
+            // count field wins whenever it can hold the number of subpackets
+            LengthStrategy::Smallest if subpackets.len() < 1 << NUMBER_OF_SUBPACKETS_SIZE => {
+                LengthMode::NumberOfSubpackets
+            }
+            LengthStrategy::Smallest => LengthMode::TotalLengthInBits,
+        };
+
+        let length_start = self.out.len() + 1;
+        match mode {
+            LengthMode::TotalLengthInBits => {
+                self.out.push(false);
+                push_bits(&mut self.out, 0, TOTAL_LENGTH_IN_BITS_SIZE);
+            }
+            LengthMode::NumberOfSubpackets => {
+                if self.strategy == LengthStrategy::Preserve && length.value() != subpackets.len() {
+                    let kind = EncodeErrorKind::DeclaredLengthMismatch {
+                        declared: *length,
+                        actual: subpackets.len(),
+                    };
+                    return Err(self.error(kind));
+                }
+                if subpackets.len() >= 1 << NUMBER_OF_SUBPACKETS_SIZE {
+                    let kind = EncodeErrorKind::SubpacketCountOverflow(subpackets.len());
+                    return Err(self.error(kind));
+                }
+                self.out.push(true);
+                push_bits(&mut self.out, subpackets.len(), NUMBER_OF_SUBPACKETS_SIZE);
+            }
+        }
+        Ok(OpenOperator {
+            length,
+            subpackets,
+            mode,
+            length_start,
+            subpackets_start: self.out.len(),
+            next_subpacket: 0,
+        })
+    }
+
+    fn finish_operator(&mut self, operator: &OpenOperator) -> Result<(), EncodeError> {
+        if operator.mode == LengthMode::NumberOfSubpackets {
+            return Ok(());
+        }
+
+        let mut total_length = self.out.len() - operator.subpackets_start;
+        if self.strategy == LengthStrategy::Preserve {
+            // a declared length a little longer than the subpackets is slack
+            // that a lenient decode skipped over, so it is padded back in with
+            // zeros, but slack long enough to hold a packet would not decode
+            // the same way
+            let declared = operator.length.value();
+            if declared < total_length || declared - total_length >= MIN_PACKET_SIZE {
+                let kind = EncodeErrorKind::DeclaredLengthMismatch {
+                    declared: *operator.length,
+                    actual: total_length,
+                };
+                return Err(self.error(kind));
+            }
+            total_length = declared;
+            self.out
+                .resize(operator.subpackets_start + total_length, false);
+        }
+        if total_length >= 1 << TOTAL_LENGTH_IN_BITS_SIZE {
+            let kind = EncodeErrorKind::TotalLengthOverflow(total_length);
+            return Err(self.error(kind));
+        }
+        self.out[operator.length_start..operator.subpackets_start].store_be(total_length);
+        Ok(())
+    }
+
+    // writes a literal whole, or the start of an operator for encode to
+    // write the subpackets of
+    fn open_packet<'p>(
+        &mut self,
+        packet: &'p Packet,
+    ) -> Result<Option<OpenOperator<'p>>, EncodeError> {
+        let packet_header = match packet.header() {
+            Some(packet_header) => packet_header,
+            None => return Err(self.error(EncodeErrorKind::InvalidPacket)),
+        };
+        let is_literal = matches!(
+            FromPrimitive::from_u8(packet_header.packet_type),
+            Some(PacketType::Literal)
+        );
+        if is_literal != matches!(packet, Packet::Literal(..)) {
+            let kind = EncodeErrorKind::MismatchedPacketType(packet_header.packet_type);
+            return Err(self.error(kind));
+        }
+
+        self.encode_header(packet_header)?;
+        match packet {
+            Packet::Literal(_packet_header, literal, ngroups) => {
+                self.encode_literal(literal, *ngroups)?;
+                Ok(None)
+            }
+            Packet::Operator(_packet_header, length, subpackets) => {
+                self.open_operator(length, subpackets).map(Some)
+            }
+            Packet::Invalid { .. } => unreachable!("invalid packets have no header"),
+        }
+    }
+
+    // keeps the operators being written on an explicit stack, like
+    // fold_iterative, so that deeply nested packets cannot overflow the call
+    // stack
+    fn encode(&mut self, packet: &Packet) -> Result<(), EncodeError> {
+        let mut stack = match self.open_packet(packet)? {
+            Some(operator) => vec![operator],
+            None => return Ok(()),
+        };
+
+        loop {
+            let operator = stack
+                .last_mut()
+                .expect("stack emptied before the root was encoded");
+            if let Some(subpacket) = operator.subpackets.get(operator.next_subpacket) {
+                self.path.push(operator.next_subpacket);
+                operator.next_subpacket += 1;
+                match self.open_packet(subpacket)? {
+                    Some(operator) => stack.push(operator),
+                    None => {
+                        self.path.pop();
+                    }
+                }
+                continue;
+            }
+
+            let operator = stack
+                .pop()
+                .expect("stack emptied before the root was encoded");
+            self.finish_operator(&operator)?;
+            if stack.is_empty() {
+                return Ok(());
+            }
+            self.path.pop();
+        }
+    }
+}
+
+pub fn encode_packet_with(
+    packet: &Packet,
+    strategy: LengthStrategy,
+) -> Result<BitVec<Msb0, u8>, EncodeError> {
+    let mut encoder = Encoder {
+        strategy,
+        path: Vec::new(),
+        out: BitVec::new(),
+    };
+    encoder.encode(packet)?;
+    Ok(encoder.out)
+}
+
+pub fn encode_packet(packet: &Packet) -> Result<BitVec<Msb0, u8>, EncodeError> {
+    encode_packet_with(packet, LengthStrategy::Preserve)
+}
+
+pub fn encode_bits_to_hex(bits: &D16BitSlice) -> String {
+    let mut padded: BitVec<Msb0, u8> = bits.iter().collect();
+    let padding = (8 - padded.len() % 8) % 8;
+    padded.resize(padded.len() + padding, false);
+    hex::encode_upper(padded.as_raw_slice())
+}
+
+pub fn encode_packet_to_hex(packet: &Packet) -> Result<String, EncodeError> {
+    encode_packet(packet).map(|bits| encode_bits_to_hex(&bits))
+}
+
+// sizes in bits of the original packet as it was sent and of the re-encoded
+// packet, both without the padding that follows them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodingSavings {
+    pub original_bits: usize,
+    pub encoded_bits: usize,
+}
+
+impl EncodingSavings {
+    // negative when the original transmission was already smaller
+    pub fn saved_bits(&self) -> isize {
+        self.original_bits as isize - self.encoded_bits as isize
+    }
+
+    // the bytes each takes once padded for sending in hex
+    pub fn original_bytes(&self) -> usize {
+        self.original_bits.div_ceil(8)
+    }
+
+    pub fn encoded_bytes(&self) -> usize {
+        self.encoded_bits.div_ceil(8)
+    }
+}
+
+impl fmt::Display for EncodingSavings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} bits down to {}, saving {} bits ({} bytes down to {})",
+            self.original_bits,
+            self.encoded_bits,
+            self.saved_bits(),
+            self.original_bytes(),
+            self.encoded_bytes()
+        )
+    }
+}
+
+// Encodes packet in as few bits as possible, choosing the length fields and
+// dropping leading zero literal groups and any slack in the original lengths.
+// original is the decoded transmission to compare against, whose bit_len is
+// what it took to send.
+pub fn encode_packet_smallest(
+    packet: &Packet,
+    original: &Packet,
+) -> Result<(BitVec<Msb0, u8>, EncodingSavings), EncodeError> {
+    let bits = encode_packet_with(packet, LengthStrategy::Smallest)?;
+    let savings = EncodingSavings {
+        original_bits: original.bit_len(),
+        encoded_bits: bits.len(),
+    };
+    Ok((bits, savings))
+}
+
+#[cfg(test)]
+use crate::decode::{decode_packet, decode_packet_from_hex};
+
+#[test]
+fn test_encode_matches_examples() {
+    for input in ["D2FE28", "38006F45291200", "EE00D40C823060"] {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        assert_eq!(encode_packet_to_hex(&packet).unwrap(), input);
+    }
+}
+
+#[test]
+fn test_encode_round_trip() {
+    let packet = Packet::operator(
+        3,
+        PacketOperatorType::Sum,
+        LengthMode::NumberOfSubpackets,
+        vec![
+            Packet::literal(0, 0u8),
+            Packet::literal(7, u64::MAX),
+            Packet::operator(
+                1,
+                PacketOperatorType::EqualTo,
+                LengthMode::TotalLengthInBits,
+                vec![Packet::literal(2, 15u8), Packet::literal(5, 16u8)],
+            ),
+            Packet::operator(
+                0,
+                PacketOperatorType::Maximum,
+                LengthMode::TotalLengthInBits,
+                vec![],
+            ),
+        ],
+    );
+
+    let bits = encode_packet(&packet).unwrap();
+    assert_eq!(bits.len(), packet.bit_len());
+    let (decoded, remaining_bits) = decode_packet(&bits).unwrap();
+    assert_eq!(decoded, packet);
+    assert!(remaining_bits.is_empty());
+
+    let hex = encode_packet_to_hex(&packet).unwrap();
+    let (decoded, _remaining_bits) = decode_packet_from_hex(&hex).unwrap();
+    assert_eq!(decoded, packet);
+
+    for mode in [
+        LengthMode::TotalLengthInBits,
+        LengthMode::NumberOfSubpackets,
+    ] {
+        let bits = encode_packet_with(&packet, LengthStrategy::Force(mode)).unwrap();
+        let (decoded, _remaining_bits) = decode_packet(&bits).unwrap();
+        assert!(decoded.length_mismatches().is_empty());
+        assert_eq!(encode_packet(&decoded).unwrap(), bits);
+    }
+
+    let bad_version = Packet::literal(8, 1u8);
+    assert_eq!(
+        encode_packet_with(&bad_version, LengthStrategy::Preserve)
+            .unwrap_err()
+            .kind,
+        EncodeErrorKind::VersionOutOfRange(8)
+    );
+    assert_eq!(
+        encode_packet_to_hex(&bad_version).unwrap_err().kind,
+        EncodeErrorKind::VersionOutOfRange(8)
+    );
+}
+
+#[test]
+fn test_encode_preserves_length_slack() {
+    // total length of 30 bits declared for 27 bits of literals, the last 3
+    // bits being too short for another packet
+    let packet_header = PacketHeader {
+        packet_version: 1,
+        packet_type: PacketOperatorType::Sum as u8,
+    };
+    let packet = Packet::Operator(
+        packet_header,
+        OperatorLength::TotalLengthInBits(30),
+        vec![Packet::literal(0, 10u8), Packet::literal(0, 20u8)],
+    );
+    let mismatches = packet.length_mismatches();
+    assert_eq!(mismatches.len(), 1);
+    assert_eq!(mismatches[0].consumed, 27);
+
+    let bits = encode_packet(&packet).unwrap();
+    let (decoded, _remaining_bits) = decode_packet(&bits).unwrap();
+    assert_eq!(decoded, packet);
+    assert_eq!(encode_packet(&decoded).unwrap(), bits);
+
+    // slack that was not zero comes back as zeros
+    let mut original = bits.clone();
+    original.set(bits.len() - 2, true);
+    let (decoded, _remaining_bits) = decode_packet(&original).unwrap();
+    assert_eq!(decoded, packet);
+    let reencoded = encode_packet(&decoded).unwrap();
+    assert_ne!(reencoded, original);
+    assert_eq!(reencoded, bits);
+
+    // slack that could hold a packet would be decoded as one
+    let packet = Packet::Operator(
+        packet_header,
+        OperatorLength::TotalLengthInBits(33),
+        vec![Packet::literal(0, 10u8), Packet::literal(0, 20u8)],
+    );
+    assert_eq!(
+        encode_packet_with(&packet, LengthStrategy::Preserve)
+            .unwrap_err()
+            .kind,
+        EncodeErrorKind::DeclaredLengthMismatch {
+            declared: OperatorLength::TotalLengthInBits(33),
+            actual: 27
+        }
+    );
+}
+
+#[test]
+fn test_encode_preserves_literal_groups() {
+    // a 4 sent as the groups 0000 and 0100, with four bits of slack after it
+    let input = "38007F45290200";
+    let original = crate::decode::decode_hex(input).unwrap();
+    let (packet, _remaining_bits) = decode_packet(original.view_bits()).unwrap();
+    assert_eq!(
+        packet.subpackets()[1],
+        Packet::Literal(
+            PacketHeader {
+                packet_version: 2,
+                packet_type: PacketType::Literal as u8,
+            },
+            Literal::from(4u8),
+            2,
+        )
+    );
+    assert_eq!(packet.bit_len(), 53);
+
+    let bits = encode_packet(&packet).unwrap();
+    assert_eq!(bits[..], original.view_bits::<Msb0>()[..bits.len()]);
+    let (decoded, _remaining_bits) = decode_packet(&bits).unwrap();
+    assert_eq!(decoded, packet);
+
+    let packet = Packet::Literal(
+        packet.subpackets()[1].header().unwrap().to_owned(),
+        Literal::from(20u8),
+        1,
+    );
+    assert_eq!(
+        encode_packet_with(&packet, LengthStrategy::Preserve)
+            .unwrap_err()
+            .kind,
+        EncodeErrorKind::TooFewLiteralGroups {
+            ngroups: 1,
+            needed: 2
+        }
+    );
+}
+
+#[test]
+fn test_encode_smallest() {
+    let input = "38006F45291200";
+    let original = crate::decode::decode_hex(input).unwrap();
+    let original = original.view_bits::<Msb0>();
+    let (packet, _remaining_bits) = decode_packet(original).unwrap();
+
+    let (bits, savings) = encode_packet_smallest(&packet, &packet).unwrap();
+    let (decoded, _remaining_bits) = decode_packet(&bits).unwrap();
+    assert_eq!(
+        crate::eval::compute_packet_result(&decoded),
+        crate::eval::compute_packet_result(&packet)
+    );
+    // the total length is replaced by a subpacket count 4 bits shorter
+    assert_eq!(bits.len(), packet.bit_len() - 4);
+    assert_eq!(
+        savings,
+        EncodingSavings {
+            original_bits: 49,
+            encoded_bits: 45,
+        }
+    );
+    assert_eq!(savings.saved_bits(), 4);
+    assert_eq!(savings.original_bytes(), 7);
+    assert_eq!(savings.encoded_bytes(), 6);
+
+    // the padding after a packet that is already minimal is not saved
+    let (packet, _remaining_bits) = decode_packet_from_hex("D2FE28").unwrap();
+    let (_bits, savings) = encode_packet_smallest(&packet, &packet).unwrap();
+    assert_eq!(savings.saved_bits(), 0);
+    assert_eq!(
+        savings.to_string(),
+        "21 bits down to 21, saving 0 bits (3 bytes down to 3)"
+    );
+
+    // a literal sent with a leading zero group and an operator with slack
+    let mut original: BitVec<Msb0, u8> = BitVec::new();
+    let fields = [
+        // sum with a 29 bit total length
+        (1, PACKET_VERSION_SIZE),
+        (0, PACKET_TYPE_SIZE),
+        (0, 1),
+        (29, TOTAL_LENGTH_IN_BITS_SIZE),
+        // 5 as the groups 0000 and 0101
+        (6, PACKET_VERSION_SIZE),
+        (4, PACKET_TYPE_SIZE),
+        (0b10000, LITERAL_GROUP_SIZE + 1),
+        (0b00101, LITERAL_GROUP_SIZE + 1),
+        // 3 and two bits of slack
+        (2, PACKET_VERSION_SIZE),
+        (4, PACKET_TYPE_SIZE),
+        (0b00011, LITERAL_GROUP_SIZE + 1),
+        (0, 2),
+    ];
+    for (value, width) in fields {
+        push_bits(&mut original, value, width);
+    }
+    let (packet, _remaining_bits) = decode_packet(&original).unwrap();
+    let (bits, savings) = encode_packet_smallest(&packet, &packet).unwrap();
+    assert_eq!(bits.len(), 7 + 11 + 11 + 11);
+    assert_eq!(
+        savings,
+        EncodingSavings {
+            original_bits: 51,
+            encoded_bits: 40,
+        }
+    );
+    assert_eq!(savings.saved_bits(), 11);
+    assert_eq!(savings.original_bytes(), 7);
+    assert_eq!(savings.encoded_bytes(), 5);
+}
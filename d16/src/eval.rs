@@ -0,0 +1,417 @@
+use crate::packet::*;
+use crate::validate::{check_packet, ValidationIssueKind};
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use num_traits::FromPrimitive;
+use std::error::Error;
+use std::fmt;
+
+struct VersionSum;
+
+impl PacketFold for VersionSum {
+    type Output = u32;
+
+    fn post(&mut self, _packet: &Packet, context: &VisitContext, subpacket_sums: Vec<u32>) -> u32 {
+        // invalid packets have no version to count
+        let packet_version_sum: u32 = context.header.map_or(0, |h| h.packet_version.into());
+        packet_version_sum + subpacket_sums.into_iter().sum::<u32>()
+    }
+}
+
+pub fn compute_version_sum(packet: &Packet) -> u32 {
+    fold_iterative(packet, &mut VersionSum)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvalErrorKind {
+    Overflow,
+    LiteralOutOfRange,
+    WrongArity { expected: Arity, found: usize },
+    UnknownType,
+    InvalidPacket,
+}
+
+// path and packet_type identify the packet that failed to evaluate, with no
+// packet_type for invalid packets
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvalError {
+    pub kind: EvalErrorKind,
+    pub path: Vec<usize>,
+    pub packet_type: Option<u8>,
+}
+
+impl fmt::Display for EvalErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalErrorKind::Overflow => write!(f, "overflowed"),
+            EvalErrorKind::LiteralOutOfRange => write!(f, "is out of range"),
+            EvalErrorKind::WrongArity { expected, found } => {
+                write!(f, "takes {} subpackets but has {}", expected, found)
+            }
+            EvalErrorKind::UnknownType => write!(f, "has an unexpected type"),
+            EvalErrorKind::InvalidPacket => write!(f, "could not be decoded"),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let type_name = match self.packet_type {
+            Some(packet_type) => {
+                let packet_header = PacketHeader {
+                    packet_version: 0,
+                    packet_type,
+                };
+                packet_header.type_name()
+            }
+            None => "invalid".to_string(),
+        };
+        let path = format_path(&self.path);
+        write!(f, "{} at {} {}", type_name, path, self.kind)
+    }
+}
+
+impl Error for EvalError {}
+
+// how sums and products are computed, None meaning the result is out of range
+pub trait Arithmetic {
+    type Value: Clone + Ord + fmt::Display;
+
+    fn literal(&self, literal: &Literal) -> Option<Self::Value>;
+    fn boolean(&self, value: bool) -> Self::Value;
+    fn add(&self, a: Self::Value, b: Self::Value) -> Option<Self::Value>;
+    fn mul(&self, a: Self::Value, b: Self::Value) -> Option<Self::Value>;
+}
+
+#[cfg(not(feature = "bigint"))]
+fn literal_to_u64(literal: &Literal) -> Option<u64> {
+    Some(*literal)
+}
+
+#[cfg(feature = "bigint")]
+fn literal_to_u64(literal: &Literal) -> Option<u64> {
+    num_traits::ToPrimitive::to_u64(literal)
+}
+
+#[cfg(not(feature = "bigint"))]
+fn literal_low_u64(literal: &Literal) -> u64 {
+    *literal
+}
+
+#[cfg(feature = "bigint")]
+fn literal_low_u64(literal: &Literal) -> u64 {
+    literal.iter_u64_digits().next().unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checked;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Wrapping;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Saturating;
+
+// u128 arithmetic, which still reports an error if even that overflows
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Widened;
+
+impl Arithmetic for Checked {
+    type Value = u64;
+
+    fn literal(&self, literal: &Literal) -> Option<u64> {
+        literal_to_u64(literal)
+    }
+
+    fn boolean(&self, value: bool) -> u64 {
+        value.into()
+    }
+
+    fn add(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_add(b)
+    }
+
+    fn mul(&self, a: u64, b: u64) -> Option<u64> {
+        a.checked_mul(b)
+    }
+}
+
+impl Arithmetic for Wrapping {
+    type Value = u64;
+
+    fn literal(&self, literal: &Literal) -> Option<u64> {
+        Some(literal_low_u64(literal))
+    }
+
+    fn boolean(&self, value: bool) -> u64 {
+        value.into()
+    }
+
+    fn add(&self, a: u64, b: u64) -> Option<u64> {
+        Some(a.wrapping_add(b))
+    }
+
+    fn mul(&self, a: u64, b: u64) -> Option<u64> {
+        Some(a.wrapping_mul(b))
+    }
+}
+
+impl Arithmetic for Saturating {
+    type Value = u64;
+
+    fn literal(&self, literal: &Literal) -> Option<u64> {
+        Some(literal_to_u64(literal).unwrap_or(u64::MAX))
+    }
+
+    fn boolean(&self, value: bool) -> u64 {
+        value.into()
+    }
+
+    fn add(&self, a: u64, b: u64) -> Option<u64> {
+        Some(a.saturating_add(b))
+    }
+
+    fn mul(&self, a: u64, b: u64) -> Option<u64> {
+        Some(a.saturating_mul(b))
+    }
+}
+
+impl Arithmetic for Widened {
+    type Value = u128;
+
+    #[cfg(not(feature = "bigint"))]
+    fn literal(&self, literal: &Literal) -> Option<u128> {
+        Some((*literal).into())
+    }
+
+    #[cfg(feature = "bigint")]
+    fn literal(&self, literal: &Literal) -> Option<u128> {
+        num_traits::ToPrimitive::to_u128(literal)
+    }
+
+    fn boolean(&self, value: bool) -> u128 {
+        value.into()
+    }
+
+    fn add(&self, a: u128, b: u128) -> Option<u128> {
+        a.checked_add(b)
+    }
+
+    fn mul(&self, a: u128, b: u128) -> Option<u128> {
+        a.checked_mul(b)
+    }
+}
+
+#[cfg(feature = "bigint")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Unbounded;
+
+#[cfg(feature = "bigint")]
+impl Arithmetic for Unbounded {
+    type Value = Literal;
+
+    fn literal(&self, literal: &Literal) -> Option<Literal> {
+        Some(literal.clone())
+    }
+
+    fn boolean(&self, value: bool) -> Literal {
+        Literal::from(value as u8)
+    }
+
+    fn add(&self, a: Literal, b: Literal) -> Option<Literal> {
+        Some(a + b)
+    }
+
+    fn mul(&self, a: Literal, b: Literal) -> Option<Literal> {
+        Some(a * b)
+    }
+}
+
+// the arithmetic whose values are Literals, used by compute_packet_result
+#[cfg(not(feature = "bigint"))]
+pub type Exact = Checked;
+#[cfg(feature = "bigint")]
+pub type Exact = Unbounded;
+
+pub(crate) struct Evaluator<'a, A: Arithmetic> {
+    pub(crate) arithmetic: &'a A,
+}
+
+impl<'a, A: Arithmetic> Evaluator<'a, A> {
+    fn error(&self, kind: EvalErrorKind, context: &VisitContext) -> EvalError {
+        EvalError {
+            kind,
+            path: context.path.to_vec(),
+            packet_type: context.header.map(|h| h.packet_type),
+        }
+    }
+
+    // the packet has been through check_packet, so the operator type is known
+    // and the number of subpackets is right for it
+    fn evaluate_operator(
+        &self,
+        context: &VisitContext,
+        subpacket_results: Vec<A::Value>,
+    ) -> Result<A::Value, EvalError> {
+        let arithmetic = self.arithmetic;
+        let overflow = || self.error(EvalErrorKind::Overflow, context);
+        let packet_header = context
+            .header
+            .expect("invalid packets rejected by check_packet");
+        let operator_type = PacketOperatorType::from_u8(packet_header.packet_type)
+            .expect("operator type checked by check_packet");
+        let mut results = subpacket_results.into_iter();
+        let mut first = results.next().expect("arity checked by check_packet");
+
+        let result: A::Value = match operator_type {
+            PacketOperatorType::Sum => {
+                for x in results {
+                    first = arithmetic.add(first, x).ok_or_else(overflow)?;
+                }
+                first
+            }
+            PacketOperatorType::Product => {
+                for x in results {
+                    first = arithmetic.mul(first, x).ok_or_else(overflow)?;
+                }
+                first
+            }
+            PacketOperatorType::Minimum => {
+                results.fold(first, |acc, x| if acc <= x { acc } else { x })
+            }
+            PacketOperatorType::Maximum => {
+                results.fold(first, |acc, x| if acc >= x { acc } else { x })
+            }
+            PacketOperatorType::GreaterThan => {
+                let second = results.next().expect("arity checked by check_packet");
+                arithmetic.boolean(first > second)
+            }
+            PacketOperatorType::LessThan => {
+                let second = results.next().expect("arity checked by check_packet");
+                arithmetic.boolean(first < second)
+            }
+            PacketOperatorType::EqualTo => {
+                let second = results.next().expect("arity checked by check_packet");
+                arithmetic.boolean(first == second)
+            }
+        };
+
+        Ok(result)
+    }
+}
+
+impl<'a, A: Arithmetic> PacketFold for Evaluator<'a, A> {
+    type Output = Result<A::Value, EvalError>;
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        context: &VisitContext,
+        subpacket_results: Vec<Self::Output>,
+    ) -> Self::Output {
+        if let Some(issue) = check_packet(packet) {
+            let kind = match issue {
+                ValidationIssueKind::WrongArity { expected, found } => {
+                    EvalErrorKind::WrongArity { expected, found }
+                }
+                ValidationIssueKind::UnknownType => EvalErrorKind::UnknownType,
+                ValidationIssueKind::InvalidPacket => EvalErrorKind::InvalidPacket,
+            };
+            return Err(self.error(kind, context));
+        }
+
+        match packet {
+            Packet::Literal(_packet_header, literal, _ngroups) => self
+                .arithmetic
+                .literal(literal)
+                .ok_or_else(|| self.error(EvalErrorKind::LiteralOutOfRange, context)),
+            Packet::Operator(..) => {
+                let subpacket_results = subpacket_results.into_iter().collect::<Result<_, _>>()?;
+                self.evaluate_operator(context, subpacket_results)
+            }
+            Packet::Invalid { .. } => unreachable!("invalid packets rejected by check_packet"),
+        }
+    }
+}
+
+pub fn evaluate<A: Arithmetic>(packet: &Packet, arithmetic: A) -> Result<A::Value, EvalError> {
+    let mut evaluator = Evaluator {
+        arithmetic: &arithmetic,
+    };
+    fold_iterative(packet, &mut evaluator)
+}
+
+pub fn compute_packet_result(packet: &Packet) -> Result<Literal, EvalError> {
+    evaluate(packet, Exact::default())
+}
+
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+
+#[test]
+fn test_known_answers() {
+    let version_sums = [
+        ("D2FE28", 6),
+        ("38006F45291200", 9),
+        ("EE00D40C823060", 14),
+        ("8A004A801A8002F478", 16),
+        ("620080001611562C8802118E34", 12),
+        ("C0015000016115A2E0802F182340", 23),
+        ("A0016C880162017C3686B18A3D4780", 31),
+    ];
+    for (input, expected) in version_sums {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        assert_eq!(compute_version_sum(&packet), expected, "{}", input);
+    }
+
+    let results: [(&str, u32); 11] = [
+        ("D2FE28", 2021),
+        ("38006F45291200", 1),
+        ("EE00D40C823060", 3),
+        ("C200B40A82", 3),
+        ("04005AC33890", 54),
+        ("880086C3E88112", 7),
+        ("CE00C43D881120", 9),
+        ("D8005AC2A8F0", 1),
+        ("F600BC2D8F", 0),
+        ("9C005AC2F8F0", 0),
+        ("9C0141080250320F1802104A08", 1),
+    ];
+    for (input, expected) in results {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        assert_eq!(
+            compute_packet_result(&packet).unwrap(),
+            Literal::from(expected),
+            "{}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_arithmetic_policies() {
+    // 2^40 * 2^40 overflows u64 but not u128
+    let big = 1u64 << 40;
+    let packet = Packet::operator(
+        0,
+        PacketOperatorType::Sum,
+        LengthMode::NumberOfSubpackets,
+        vec![
+            Packet::literal(0, 1u8),
+            Packet::operator(
+                0,
+                PacketOperatorType::Product,
+                LengthMode::NumberOfSubpackets,
+                vec![Packet::literal(0, big), Packet::literal(0, big)],
+            ),
+        ],
+    );
+
+    let error = evaluate(&packet, Checked).unwrap_err();
+    assert_eq!(error.kind, EvalErrorKind::Overflow);
+    assert_eq!(error.path, vec![1]);
+    assert_eq!(error.to_string(), "product at /1 overflowed");
+
+    assert_eq!(evaluate(&packet, Wrapping).unwrap(), 1);
+    assert_eq!(evaluate(&packet, Saturating).unwrap(), u64::MAX);
+    assert_eq!(evaluate(&packet, Widened).unwrap(), (1u128 << 80) + 1);
+}
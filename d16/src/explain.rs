@@ -0,0 +1,234 @@
+use crate::eval::{Arithmetic, EvalError, Evaluator, Exact};
+use crate::packet::*;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use num_traits::FromPrimitive;
+use std::fmt;
+
+// one operator that evaluated, with the values of its subpackets in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplainStep<V> {
+    pub path: Vec<usize>,
+    pub bit_offset: usize,
+    pub operator_type: PacketOperatorType,
+    pub operands: Vec<V>,
+    pub result: V,
+}
+
+// steps are in the order the operators appear in the transmission, and stop
+// short of whichever operator failed when result is an error
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<V> {
+    pub steps: Vec<ExplainStep<V>>,
+    pub result: Result<V, EvalError>,
+}
+
+impl<V: fmt::Display> fmt::Display for ExplainStep<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operands: Vec<String> = self.operands.iter().map(|v| v.to_string()).collect();
+        let symbol = self.operator_type.symbol();
+        let expression = if self.operator_type.is_infix() && operands.len() > 1 {
+            operands.join(&format!(" {} ", symbol))
+        } else if self.operator_type.is_infix() {
+            format!("{}({})", self.operator_type.name(), operands.join(", "))
+        } else {
+            format!("{}({})", symbol, operands.join(", "))
+        };
+        write!(
+            f,
+            "{} at bit {}: {} = {}",
+            self.operator_type.name(),
+            self.bit_offset,
+            expression,
+            self.result
+        )
+    }
+}
+
+impl<V: fmt::Display> fmt::Display for Explanation<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for step in &self.steps {
+            writeln!(f, "{}{}", "  ".repeat(step.path.len()), step)?;
+        }
+        match &self.result {
+            Ok(result) => writeln!(f, "result {}", result),
+            Err(e) => writeln!(f, "error: {}", e),
+        }
+    }
+}
+
+struct Explainer<'a, A: Arithmetic> {
+    evaluator: Evaluator<'a, A>,
+    offsets: &'a PacketOffsets,
+    // how many packets pre has seen, which numbers them as offsets does, and
+    // the starts of the packets being visited
+    visited: usize,
+    starts: Vec<usize>,
+    steps: Vec<ExplainStep<A::Value>>,
+}
+
+impl<'a, A: Arithmetic> PacketFold for Explainer<'a, A> {
+    type Output = Result<A::Value, EvalError>;
+
+    fn pre(&mut self, _packet: &Packet, _context: &VisitContext) {
+        let start = self.offsets.nth(self.visited);
+        self.starts.push(start.expect("offsets cover every packet"));
+        self.visited += 1;
+    }
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        context: &VisitContext,
+        subpacket_results: Vec<Self::Output>,
+    ) -> Self::Output {
+        let start = self.starts.pop().expect("pre pushed a start");
+        let operands: Option<Vec<A::Value>> = subpacket_results
+            .iter()
+            .map(|result| result.as_ref().ok().cloned())
+            .collect();
+        let result = self.evaluator.post(packet, context, subpacket_results);
+
+        let operator_type = context
+            .header
+            .and_then(|packet_header| PacketOperatorType::from_u8(packet_header.packet_type));
+        if let (Packet::Operator(..), Some(operator_type), Some(operands), Ok(value)) =
+            (packet, operator_type, operands, &result)
+        {
+            self.steps.push(ExplainStep {
+                path: context.path.to_vec(),
+                bit_offset: start,
+                operator_type,
+                operands,
+                result: value.clone(),
+            });
+        }
+        result
+    }
+}
+
+// offsets are where the packets of packet were decoded from, see
+// decode_packet_with_offsets, and None if they are the offsets of some other
+// tree
+pub fn explain_with_offsets<A: Arithmetic>(
+    packet: &Packet,
+    offsets: &PacketOffsets,
+    arithmetic: A,
+) -> Option<Explanation<A::Value>> {
+    if !offsets.fit(packet) {
+        return None;
+    }
+    Some(explain_at(packet, offsets, arithmetic))
+}
+
+fn explain_at<A: Arithmetic>(
+    packet: &Packet,
+    offsets: &PacketOffsets,
+    arithmetic: A,
+) -> Explanation<A::Value> {
+    let mut explainer = Explainer {
+        evaluator: Evaluator {
+            arithmetic: &arithmetic,
+        },
+        offsets,
+        visited: 0,
+        starts: Vec::new(),
+        steps: Vec::new(),
+    };
+    let result = fold_iterative(packet, &mut explainer);
+
+    // steps are recorded as each operator finishes, sorting by path puts every
+    // operator before its subpackets
+    let mut steps = explainer.steps;
+    steps.sort_by(|a, b| a.path.cmp(&b.path));
+    Explanation { steps, result }
+}
+
+// reports offsets as though packet was encoded from bit 0
+pub fn explain_with<A: Arithmetic>(packet: &Packet, arithmetic: A) -> Explanation<A::Value> {
+    explain_at(packet, &PacketOffsets::from_layout(packet, 0), arithmetic)
+}
+
+// explains compute_packet_result
+pub fn explain(packet: &Packet) -> Explanation<Literal> {
+    explain_with(packet, Exact::default())
+}
+
+#[cfg(test)]
+use crate::decode::{decode_packet_from_hex, decode_packet_with_offsets, DecodeOptions};
+#[cfg(test)]
+use crate::eval::{compute_packet_result, Checked, EvalErrorKind};
+
+#[test]
+fn test_explain() {
+    let (packet, _remaining_bits) = decode_packet_from_hex("9C0141080250320F1802104A08").unwrap();
+    let explanation = explain(&packet);
+    let expected = "\
+equal_to at bit 0: 4 == 4 = 1
+  sum at bit 22: 1 + 3 = 4
+  product at bit 62: 2 * 2 = 4
+result 1
+";
+    assert_eq!(explanation.to_string(), expected);
+    assert_eq!(explanation.result, compute_packet_result(&packet));
+
+    let (packet, _remaining_bits) = decode_packet_from_hex("880086C3E88112").unwrap();
+    let explanation = explain(&packet);
+    assert_eq!(
+        explanation.steps[0].to_string(),
+        "minimum at bit 0: min(7, 8, 9) = 7"
+    );
+
+    // the steps below the failing operator are still there
+    let big = 1u64 << 40;
+    let packet = Packet::operator(
+        0,
+        PacketOperatorType::Maximum,
+        LengthMode::NumberOfSubpackets,
+        vec![
+            Packet::operator(
+                0,
+                PacketOperatorType::Sum,
+                LengthMode::NumberOfSubpackets,
+                vec![Packet::literal(0, 1u8)],
+            ),
+            Packet::operator(
+                0,
+                PacketOperatorType::Product,
+                LengthMode::NumberOfSubpackets,
+                vec![Packet::literal(0, big), Packet::literal(0, big)],
+            ),
+        ],
+    );
+    let explanation = explain_with(&packet, Checked);
+    assert_eq!(explanation.steps.len(), 1);
+    assert_eq!(
+        explanation.steps[0].to_string(),
+        "sum at bit 18: sum(1) = 1"
+    );
+    assert_eq!(
+        explanation.result.unwrap_err().kind,
+        EvalErrorKind::Overflow
+    );
+}
+
+#[test]
+fn test_explain_with_offsets() {
+    let bits = crate::decode::padded_literal_sum();
+    let (packet, offsets, _remaining_bits) =
+        decode_packet_with_offsets(&bits, &DecodeOptions::default()).unwrap();
+    assert_eq!(offsets.get(&[1]), Some(39));
+    assert_eq!(offsets.get(&[1, 0]), Some(57));
+    assert_eq!(offsets, PacketOffsets::from_layout(&packet, 0));
+
+    let explanation = explain_with_offsets(&packet, &offsets, Exact::default()).unwrap();
+    assert_eq!(
+        explanation.steps[1].to_string(),
+        "sum at bit 39: sum(1) = 1"
+    );
+
+    // offsets for a different tree do not fit
+    let (literal, _remaining_bits) = decode_packet_from_hex("D2FE28").unwrap();
+    let literal_offsets = PacketOffsets::from_layout(&literal, 0);
+    assert!(explain_with_offsets(&packet, &literal_offsets, Exact::default()).is_none());
+    assert!(explain_with_offsets(&literal, &offsets, Exact::default()).is_none());
+}
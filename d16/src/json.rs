@@ -0,0 +1,374 @@
+use crate::decode::DecodeLimits;
+use crate::packet::*;
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize, Serializer};
+use std::ops::Range;
+
+// headers are written as {"version": 6, "type": "literal"}, with the type
+// given by its name
+#[derive(Serialize, Deserialize)]
+pub(crate) struct HeaderRepr {
+    version: u8,
+    #[serde(rename = "type")]
+    type_name: String,
+}
+
+impl From<PacketHeader> for HeaderRepr {
+    fn from(packet_header: PacketHeader) -> Self {
+        HeaderRepr {
+            version: packet_header.packet_version,
+            type_name: packet_header.type_name(),
+        }
+    }
+}
+
+impl TryFrom<HeaderRepr> for PacketHeader {
+    type Error = String;
+
+    fn try_from(repr: HeaderRepr) -> Result<Self, Self::Error> {
+        let packet_type = packet_type_from_name(&repr.type_name)
+            .ok_or_else(|| format!("unknown packet type {:?}", repr.type_name))?;
+        Ok(PacketHeader {
+            packet_version: repr.version,
+            packet_type,
+        })
+    }
+}
+
+// without bigint literals are plain JSON numbers, with it the ones too large
+// for a u64 are written as decimal strings
+#[cfg(not(feature = "bigint"))]
+type LiteralRepr = Literal;
+
+#[cfg(feature = "bigint")]
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum LiteralRepr {
+    Number(u64),
+    Text(String),
+}
+
+#[cfg(not(feature = "bigint"))]
+fn literal_to_repr(literal: &Literal) -> LiteralRepr {
+    *literal
+}
+
+#[cfg(not(feature = "bigint"))]
+fn literal_from_repr(repr: LiteralRepr) -> Result<Literal, String> {
+    Ok(repr)
+}
+
+#[cfg(feature = "bigint")]
+fn literal_to_repr(literal: &Literal) -> LiteralRepr {
+    match u64::try_from(literal) {
+        Ok(number) => LiteralRepr::Number(number),
+        Err(_) => LiteralRepr::Text(literal.to_string()),
+    }
+}
+
+#[cfg(feature = "bigint")]
+fn literal_from_repr(repr: LiteralRepr) -> Result<Literal, String> {
+    match repr {
+        LiteralRepr::Number(number) => Ok(number.into()),
+        LiteralRepr::Text(text) => text
+            .parse()
+            .map_err(|_| format!("invalid literal {:?}", text)),
+    }
+}
+
+// literals carry a value, plus a group count when they were sent with leading
+// zero groups, operators a length and their subpackets; a missing length is
+// filled in as a subpacket count. Invalid packets are written as
+// {"type": "invalid", "bit_range": {"start": 3, "end": 40}, "reason": "..."}
+// without a version, and cannot be read back since the reason is only text.
+#[derive(Deserialize)]
+pub(crate) struct PacketRepr {
+    #[serde(default)]
+    version: Option<u8>,
+    #[serde(rename = "type")]
+    type_name: String,
+    #[serde(default)]
+    value: Option<LiteralRepr>,
+    #[serde(default)]
+    groups: Option<usize>,
+    #[serde(default)]
+    length: Option<OperatorLength>,
+    #[serde(default)]
+    subpackets: Option<Vec<Packet>>,
+    #[serde(default)]
+    bit_range: Option<Range<usize>>,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+// the same fields written from a borrowed packet, since serde's into would
+// clone every subtree once for each level above it
+#[derive(Serialize)]
+struct PacketReprRef<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u8>,
+    #[serde(rename = "type")]
+    type_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<LiteralRepr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    groups: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<OperatorLength>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subpackets: Option<&'a [Packet]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bit_range: Option<&'a Range<usize>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+}
+
+impl<'a> From<&'a Packet> for PacketReprRef<'a> {
+    fn from(packet: &'a Packet) -> Self {
+        let repr = |packet_header: &PacketHeader| {
+            let HeaderRepr { version, type_name } = (*packet_header).into();
+            PacketReprRef {
+                version: Some(version),
+                type_name,
+                value: None,
+                groups: None,
+                length: None,
+                subpackets: None,
+                bit_range: None,
+                reason: None,
+            }
+        };
+        match packet {
+            Packet::Literal(packet_header, literal, ngroups) => PacketReprRef {
+                groups: Some(*ngroups).filter(|&ngroups| ngroups != literal_groups(literal)),
+                value: Some(literal_to_repr(literal)),
+                ..repr(packet_header)
+            },
+            Packet::Operator(packet_header, length, subpackets) => PacketReprRef {
+                length: Some(*length),
+                subpackets: Some(subpackets),
+                ..repr(packet_header)
+            },
+            Packet::Invalid { bit_range, reason } => PacketReprRef {
+                version: None,
+                type_name: "invalid".to_string(),
+                value: None,
+                groups: None,
+                length: None,
+                subpackets: None,
+                bit_range: Some(bit_range),
+                reason: Some(reason.to_string()),
+            },
+        }
+    }
+}
+
+impl Serialize for Packet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PacketReprRef::from(self).serialize(serializer)
+    }
+}
+
+impl TryFrom<PacketRepr> for Packet {
+    type Error = String;
+
+    fn try_from(repr: PacketRepr) -> Result<Self, Self::Error> {
+        if repr.type_name == "invalid" {
+            return Err("invalid packets cannot be read back".to_string());
+        }
+        if repr.bit_range.is_some() || repr.reason.is_some() {
+            return Err("only invalid packets have a bit range and reason".to_string());
+        }
+        let packet_header = PacketHeader::try_from(HeaderRepr {
+            version: repr.version.ok_or("packets need a version")?,
+            type_name: repr.type_name,
+        })?;
+        let is_literal = matches!(
+            FromPrimitive::from_u8(packet_header.packet_type),
+            Some(PacketType::Literal)
+        );
+
+        match (is_literal, repr.value, repr.length, repr.subpackets) {
+            (true, Some(value), None, None) => {
+                let literal = literal_from_repr(value)?;
+                let needed = literal_groups(&literal);
+                match repr.groups {
+                    Some(ngroups) if ngroups < needed => Err(format!(
+                        "literal {} needs {} groups, not {}",
+                        literal, needed, ngroups
+                    )),
+                    ngroups => Ok(Packet::Literal(
+                        packet_header,
+                        literal,
+                        ngroups.unwrap_or(needed),
+                    )),
+                }
+            }
+            (true, ..) => Err("literal packets need a value and nothing else".to_string()),
+            (false, None, _, _) if repr.groups.is_some() => {
+                Err("only literal packets have groups".to_string())
+            }
+            (false, None, length, Some(subpackets)) => {
+                let length = length.unwrap_or(OperatorLength::NumberOfSubpackets(subpackets.len()));
+                Ok(Packet::Operator(packet_header, length, subpackets))
+            }
+            (false, ..) => Err("operator packets need subpackets and no value".to_string()),
+        }
+    }
+}
+
+// Packet trees are written and read one nesting level at a time, so both
+// grow the stack as they go rather than overflow it on deep trees.
+pub fn packet_to_json(packet: &Packet) -> String {
+    let mut json = Vec::new();
+    let mut serializer = serde_json::Serializer::pretty(&mut json);
+    packet
+        .serialize(serde_stacker::Serializer::new(&mut serializer))
+        .expect("packets always serialize");
+    String::from_utf8(json).expect("serde_json writes UTF-8")
+}
+
+// the deepest nesting of arrays and objects in input, found without parsing
+// it so that nothing recurses
+fn json_depth(input: &str) -> usize {
+    let mut depth: usize = 0;
+    let mut deepest = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in input.chars() {
+        match (in_string, c) {
+            (true, _) if escaped => escaped = false,
+            (true, '\\') => escaped = true,
+            (true, '"') => in_string = false,
+            (true, _) => {}
+            (false, '"') => in_string = true,
+            (false, '[' | '{') => {
+                depth += 1;
+                deepest = deepest.max(depth);
+            }
+            (false, ']' | '}') => depth = depth.saturating_sub(1),
+            (false, _) => {}
+        }
+    }
+    deepest
+}
+
+// Rejects trees nested deeper than limits.max_depth, the only limit that
+// applies, as the decoder would have. Each packet takes two levels of JSON,
+// its object and its subpackets array, and its length one more.
+pub fn packet_from_json_with(
+    input: &str,
+    limits: &DecodeLimits,
+) -> Result<Packet, serde_json::Error> {
+    if let Some(max_depth) = limits.max_depth {
+        if json_depth(input) > 2 * max_depth + 2 {
+            let message = format!("packets nested deeper than {} levels", max_depth);
+            return Err(serde::de::Error::custom(message));
+        }
+    }
+    let mut deserializer = serde_json::Deserializer::from_str(input);
+    deserializer.disable_recursion_limit();
+    let packet = Packet::deserialize(serde_stacker::Deserializer::new(&mut deserializer))?;
+    deserializer.end()?;
+    Ok(packet)
+}
+
+// reads back anything packet_to_json wrote for a tree decoded with the default
+// limits
+pub fn packet_from_json(input: &str) -> Result<Packet, serde_json::Error> {
+    packet_from_json_with(input, &DecodeLimits::default())
+}
+
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+#[cfg(test)]
+use crate::encode::encode_packet_to_hex;
+
+#[test]
+fn test_json_round_trip() {
+    for input in [
+        "D2FE28",
+        "38006F45291200",
+        "9C0141080250320F1802104A08",
+        "38007F45290200",
+    ] {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        let json = packet_to_json(&packet);
+        assert_eq!(packet_from_json(&json).unwrap(), packet);
+        assert_eq!(
+            encode_packet_to_hex(&packet_from_json(&json).unwrap()).unwrap(),
+            input
+        );
+    }
+
+    let (packet, _remaining_bits) = decode_packet_from_hex("38006F45291200").unwrap();
+    let expected = r#"{"version":1,"type":"less_than","length":{"total_length_in_bits":27},"subpackets":[{"version":6,"type":"literal","value":10},{"version":2,"type":"literal","value":20}]}"#;
+    assert_eq!(serde_json::to_string(&packet).unwrap(), expected);
+
+    let packet = packet_from_json(
+        r#"{"version": 3, "type": "maximum", "subpackets": [
+            {"version": 0, "type": "literal", "value": 5}
+        ]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        packet,
+        Packet::operator(
+            3,
+            PacketOperatorType::Maximum,
+            LengthMode::NumberOfSubpackets,
+            vec![Packet::literal(0, 5u8)]
+        )
+    );
+
+    for input in [
+        r#"{"version": 0, "type": "average", "subpackets": []}"#,
+        r#"{"version": 0, "type": "literal", "subpackets": []}"#,
+        r#"{"version": 0, "type": "sum", "value": 1}"#,
+        r#"{"version": 0, "type": "literal", "value": 20, "groups": 1}"#,
+        r#"{"version": 0, "type": "type_200", "subpackets": []}"#,
+    ] {
+        assert!(packet_from_json(input).is_err(), "{}", input);
+    }
+
+    #[cfg(feature = "bigint")]
+    {
+        let packet = Packet::literal(0, Literal::from(1u8) << 70);
+        let json = serde_json::to_string(&packet).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":0,"type":"literal","value":"1180591620717411303424"}"#
+        );
+        assert_eq!(packet_from_json(&json).unwrap(), packet);
+    }
+}
+
+#[test]
+fn test_json_depth_limit() {
+    let nested = |depth| {
+        (0..depth).fold(Packet::literal(0, 1u8), |packet, _| {
+            Packet::operator(
+                0,
+                PacketOperatorType::Sum,
+                LengthMode::TotalLengthInBits,
+                vec![packet],
+            )
+        })
+    };
+    // as deep as the default limits let the decoder go
+    let packet = nested(1024);
+    let json = packet_to_json(&packet);
+    assert_eq!(packet_from_json(&json).unwrap(), packet);
+
+    let json = packet_to_json(&nested(1025));
+    let error = packet_from_json(&json).unwrap_err();
+    assert_eq!(error.to_string(), "packets nested deeper than 1024 levels");
+    let limits = DecodeLimits {
+        max_depth: None,
+        ..DecodeLimits::default()
+    };
+    assert!(packet_from_json_with(&json, &limits).is_ok());
+
+    // brackets in strings do not count
+    assert_eq!(json_depth(r#"{"type": "[[[\"{"}"#), 1);
+}
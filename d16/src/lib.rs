@@ -0,0 +1,60 @@
+pub mod compile;
+pub mod decode;
+pub mod diff;
+pub mod disasm;
+pub mod dot;
+pub mod encode;
+pub mod eval;
+pub mod explain;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod packet;
+pub mod query;
+pub mod render;
+pub mod simplify;
+pub mod stats;
+pub mod validate;
+pub mod visit;
+
+pub use crate::compile::{
+    compile_expression, compile_expression_to_hex, CompileError, CompileErrorKind, CompileOptions,
+    LengthModeStrategy, VersionStrategy, MAX_EXPRESSION_DEPTH,
+};
+pub use crate::decode::input::{
+    decode_input, decode_packet_from_input, decode_packet_from_input_with, detect_format,
+    InputFormat,
+};
+pub use crate::decode::reader::{read_packets, read_packets_with, ReaderFormat, ReaderSource};
+pub use crate::decode::stream::{
+    decode_packets_from_hex, decode_packets_from_hex_with, PacketStream, StreamedPacket,
+};
+pub use crate::decode::{
+    check_padding, decode_hex, decode_packet, decode_packet_from_hex, decode_packet_from_hex_with,
+    decode_packet_from_source, decode_packet_recovering, decode_packet_recovering_from_source,
+    decode_packet_recovering_with, decode_packet_recovering_with_offsets, decode_packet_with,
+    decode_packet_with_offsets, BitSource, DecodeError, DecodeErrorKind, DecodeLimits,
+    DecodeOptions, Field, FieldKind, SliceSource, Strictness,
+};
+pub use crate::diff::{diff_packets, Change, ChangeKind, PacketDiff};
+pub use crate::disasm::{disassemble, disassemble_hex, disassemble_with, Disassembly};
+pub use crate::dot::to_dot;
+pub use crate::encode::{
+    encode_bits_to_hex, encode_packet, encode_packet_smallest, encode_packet_to_hex,
+    encode_packet_with, EncodeError, EncodeErrorKind, EncodingSavings, LengthStrategy,
+};
+#[cfg(feature = "bigint")]
+pub use crate::eval::Unbounded;
+pub use crate::eval::{
+    compute_packet_result, compute_version_sum, evaluate, Arithmetic, Checked, EvalError,
+    EvalErrorKind, Exact, Saturating, Widened, Wrapping,
+};
+pub use crate::explain::{explain, explain_with, explain_with_offsets, ExplainStep, Explanation};
+#[cfg(feature = "serde")]
+pub use crate::json::{packet_from_json, packet_from_json_with, packet_to_json};
+pub use crate::packet::*;
+pub use crate::query::{select, Query, QueryError, QueryErrorKind, QueryMatch};
+pub use crate::render::{to_infix, to_sexpr};
+pub use crate::simplify::simplify;
+pub use crate::stats::{packet_stats, BitBudget, PacketStats};
+pub use crate::validate::{validate, ValidationIssue, ValidationIssueKind};
+pub use crate::visit::{fold_iterative, fold_recursive, PacketFold, VisitContext};
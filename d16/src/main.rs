@@ -1,265 +1,407 @@
 use bitvec::prelude::*;
-use num_derive::FromPrimitive;
-use num_traits::FromPrimitive;
-use std::fs;
-
-const MIN_PACKET_SIZE: usize = 6;
-const PACKET_VERSION_SIZE: usize = 3;
-const PACKET_TYPE_SIZE: usize = 3;
-const LITERAL_GROUP_SIZE: usize = 4;
+use d16::*;
+use std::io::{self, Read};
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: d16 COMMAND [OPTIONS] [FILE]
+
+commands:
+  decode     print the decoded transmission as an infix expression
+  eval       print the value of the transmission
+  versions   print the sum of all packet versions
+  disasm     list every field with its bit range
+  stats      print packet counts, depths and where the bits go
+  dump       print the decoded transmission, see --format
+  encode     compile an infix expression into hex
+  query      list the packets a query such as //sum/literal[value>9] selects,
+             as d16 query QUERY [FILE]
+  diff       compare the transmissions in two files, as d16 diff OLD NEW
+
+The transmission or expression is read from FILE, from --hex or from stdin.
+
+options:
+  --hex HEX               use HEX as the transmission
+  --input-format FORMAT   hex, base64, bits or binary, detected if not given
+  --strict                reject trailing data and bad padding
+  --recover               decode what can be decoded, marking the rest invalid
+  --format FORMAT         for dump, one of json, sexpr, infix or dot
+  --versions              for dump, annotate packets with their versions
+  --explain               for eval, show how every operator was evaluated
+  --version V             for encode, the version of every packet, or cycle
+  --length MODE           for encode, one of total, count, alternate or smallest
+  --original FILE         for encode with --length smallest, report the bits
+                          saved over the transmission in FILE
+  --json                  for encode, read a JSON packet tree instead";
+
+// exit codes are 1 for transmissions that fail to decode, evaluate or encode
+// and 2 for bad command lines or unreadable input, help is an error with code 0
+struct CliError {
+    message: String,
+    code: i32,
+}
 
-type Literal = u64;
+impl CliError {
+    fn usage(message: impl Into<String>) -> CliError {
+        CliError {
+            message: format!("{}\n\n{}", message.into(), USAGE),
+            code: 2,
+        }
+    }
 
-struct PacketHeader {
-    packet_version: u8,
-    packet_type: u8,
-}
+    // for options that need a feature this binary was built without
+    #[cfg(not(feature = "serde"))]
+    fn unavailable(message: &str) -> CliError {
+        CliError {
+            message: message.to_string(),
+            code: 2,
+        }
+    }
 
-enum Packet {
-    Literal(PacketHeader, Literal),
-    Operator(PacketHeader, Vec<Packet>),
-}
+    // for arguments whose own syntax is wrong, where the usage would not help
+    fn invalid(message: impl ToString) -> CliError {
+        CliError {
+            message: message.to_string(),
+            code: 2,
+        }
+    }
 
-#[derive(FromPrimitive)]
-enum PacketType {
-    Literal = 4,
+    fn failed(message: impl ToString) -> CliError {
+        CliError {
+            message: message.to_string(),
+            code: 1,
+        }
+    }
 }
 
-#[derive(FromPrimitive)]
-enum PacketOperatorType {
-    Sum = 0,
-    Product,
-    Minimum,
-    Maximum,
-    GreaterThan = 5,
-    LessThan,
-    EqualTo,
+#[derive(Default)]
+struct Args {
+    command: String,
+    file: Option<String>,
+    other_file: Option<String>,
+    query: Option<String>,
+    hex: Option<String>,
+    input_format: Option<InputFormat>,
+    strict: bool,
+    recover: bool,
+    format: Option<String>,
+    versions: bool,
+    version: Option<String>,
+    length: Option<String>,
+    original: Option<String>,
+    json: bool,
+    explain: bool,
 }
 
-type D16BitSlice = BitSlice<Msb0, u8>;
-
-fn decode_literal(bits: &D16BitSlice) -> (Literal, &D16BitSlice) {
-    let mut result = bitvec![Msb0,u8;];
-    let mut ngroups = 0;
-    let mut zero_bit_group_found = false;
-
-    for (i, chunk) in bits.chunks_exact(LITERAL_GROUP_SIZE + 1).enumerate() {
-        let more_groups = chunk[0];
-        let group_bits = &chunk[1..LITERAL_GROUP_SIZE + 1];
-
-        result.extend(group_bits);
-        ngroups = i + 1;
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, CliError> {
+    let mut parsed = Args {
+        command: args
+            .next()
+            .ok_or_else(|| CliError::usage("missing command"))?,
+        ..Args::default()
+    };
+    let value = |args: &mut dyn Iterator<Item = String>, option: &str| {
+        args.next()
+            .ok_or_else(|| CliError::usage(format!("{} needs a value", option)))
+    };
 
-        if !more_groups {
-            zero_bit_group_found = true;
-            break;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hex" => parsed.hex = Some(value(&mut args, &arg)?),
+            "--input-format" => {
+                let name = value(&mut args, &arg)?;
+                let format = InputFormat::from_name(&name)
+                    .ok_or_else(|| CliError::usage(format!("unknown input format {}", name)))?;
+                parsed.input_format = Some(format);
+            }
+            "--strict" => parsed.strict = true,
+            "--recover" => parsed.recover = true,
+            "--format" => parsed.format = Some(value(&mut args, &arg)?),
+            "--versions" => parsed.versions = true,
+            "--version" => parsed.version = Some(value(&mut args, &arg)?),
+            "--length" => parsed.length = Some(value(&mut args, &arg)?),
+            "--original" => parsed.original = Some(value(&mut args, &arg)?),
+            "--json" => parsed.json = true,
+            "--explain" => parsed.explain = true,
+            "-h" | "--help" => {
+                return Err(CliError {
+                    message: USAGE.to_string(),
+                    code: 0,
+                })
+            }
+            _ if arg.starts_with("--") => {
+                return Err(CliError::usage(format!("unknown option {}", arg)))
+            }
+            _ if parsed.command == "query" && parsed.query.is_none() => parsed.query = Some(arg),
+            _ if parsed.file.is_none() => parsed.file = Some(arg),
+            _ if parsed.command == "diff" && parsed.other_file.is_none() => {
+                parsed.other_file = Some(arg)
+            }
+            _ => return Err(CliError::usage(format!("unexpected argument {}", arg))),
         }
     }
 
-    assert!(zero_bit_group_found);
-
-    let remaining_bits = &bits[ngroups * (LITERAL_GROUP_SIZE + 1)..];
-    return (result.load_be::<Literal>(), remaining_bits);
+    if parsed.hex.is_some() && parsed.file.is_some() {
+        return Err(CliError::usage("give either --hex or a file, not both"));
+    }
+    Ok(parsed)
 }
 
-fn decode_operator_for_bits(bits: &D16BitSlice) -> (Vec<Packet>, &D16BitSlice) {
-    const TOTAL_LENGTH_IN_BITS_SIZE: usize = 15;
-    let total_length = bits[..TOTAL_LENGTH_IN_BITS_SIZE].load_be::<usize>();
-    let mut remaining_bits =
-        &bits[TOTAL_LENGTH_IN_BITS_SIZE..TOTAL_LENGTH_IN_BITS_SIZE + total_length];
-    let all_remaining_bits = &bits[TOTAL_LENGTH_IN_BITS_SIZE + total_length..];
-
-    let mut result: Vec<Packet> = Vec::new();
+fn read_input(args: &Args) -> Result<Vec<u8>, CliError> {
+    read_input_from(args, args.file.as_deref())
+}
 
-    while remaining_bits.len() >= MIN_PACKET_SIZE {
-        let (new_packet, bits) = decode_packet(remaining_bits);
-        remaining_bits = bits;
-        result.push(new_packet);
+fn read_input_from(args: &Args, file: Option<&str>) -> Result<Vec<u8>, CliError> {
+    let read_error = |e: io::Error| CliError {
+        message: format!("unable to read input: {}", e),
+        code: 2,
+    };
+    match (file, &args.hex) {
+        (Some(file), _) => fs::read(file).map_err(read_error),
+        (None, Some(hex)) => Ok(hex.as_bytes().to_vec()),
+        (None, None) => {
+            let mut input = Vec::new();
+            io::stdin().read_to_end(&mut input).map_err(read_error)?;
+            Ok(input)
+        }
     }
-
-    (result, all_remaining_bits)
 }
 
-fn decode_operator_by_subpackets(bits: &D16BitSlice) -> (Vec<Packet>, &D16BitSlice) {
-    const NUMBER_OF_SUBPACKETS_SIZE: usize = 11;
-    let nsubpackets = bits[..NUMBER_OF_SUBPACKETS_SIZE].load_be::<usize>();
-    let mut remaining_bits = &bits[NUMBER_OF_SUBPACKETS_SIZE..];
+fn read_bits(args: &Args) -> Result<BitVec<Msb0, u8>, CliError> {
+    read_bits_from(args, args.file.as_deref())
+}
 
-    let mut result: Vec<Packet> = Vec::new();
+fn read_bits_from(args: &Args, file: Option<&str>) -> Result<BitVec<Msb0, u8>, CliError> {
+    let input = read_input_from(args, file)?;
+    let format = match (file, &args.hex) {
+        (None, Some(_)) => Some(args.input_format.unwrap_or(InputFormat::Hex)),
+        _ => args.input_format,
+    };
+    decode_input(&input, format).map_err(CliError::failed)
+}
 
-    for _ in 0..nsubpackets {
-        let (new_packet, bits) = decode_packet(remaining_bits);
-        remaining_bits = bits;
-        result.push(new_packet);
+fn decode_options(args: &Args) -> DecodeOptions {
+    match args.strict {
+        true => DecodeOptions::strict(),
+        false => DecodeOptions::default(),
     }
-
-    (result, remaining_bits)
 }
 
-fn decode_operator(bits: &D16BitSlice) -> (Vec<Packet>, &D16BitSlice) {
-    let rest = &bits[1..];
-    let result = if bits[0] {
-        decode_operator_by_subpackets(rest)
-    } else {
-        decode_operator_for_bits(rest)
-    };
+fn read_packet(args: &Args) -> Result<Packet, CliError> {
+    read_packet_from(args, args.file.as_deref())
+}
 
-    return result;
+fn read_packet_from(args: &Args, file: Option<&str>) -> Result<Packet, CliError> {
+    let (packet, _offsets) = read_packet_with_offsets_from(args, file)?;
+    Ok(packet)
 }
 
-fn decode_packet(packet_bits: &D16BitSlice) -> (Packet, &D16BitSlice) {
-    const LITERAL_START: usize = PACKET_VERSION_SIZE + PACKET_TYPE_SIZE;
+fn read_packet_with_offsets_from(
+    args: &Args,
+    file: Option<&str>,
+) -> Result<(Packet, PacketOffsets), CliError> {
+    let bits = read_bits_from(args, file)?;
+    decode_bits(args, &bits)
+}
 
-    let packet_type = packet_bits[PACKET_VERSION_SIZE..LITERAL_START].load_be::<u8>();
-    let packet_header = PacketHeader {
-        packet_version: packet_bits[0..PACKET_VERSION_SIZE].load_be::<u8>(),
-        packet_type: packet_type,
+// with --recover nothing fails to decode, though --strict still checks that
+// only padding follows the packet
+fn decode_bits(args: &Args, bits: &D16BitSlice) -> Result<(Packet, PacketOffsets), CliError> {
+    let options = decode_options(args);
+    let (packet, offsets, remaining_bits) = if args.recover {
+        decode_packet_recovering_with_offsets(bits, &options)
+    } else {
+        decode_packet_with_offsets(bits, &options).map_err(CliError::failed)?
     };
-    let packet_contents = &packet_bits[LITERAL_START..];
+    if args.strict {
+        check_padding(bits, remaining_bits).map_err(CliError::failed)?;
+    }
+    Ok((packet, offsets))
+}
 
-    match FromPrimitive::from_u8(packet_type) {
-        Some(PacketType::Literal) => {
-            let (literal, remaining_bits) = decode_literal(packet_contents);
-            return (Packet::Literal(packet_header, literal), remaining_bits);
-        }
-        None => {
-            let (subpackets, remaining_bits) = decode_operator(packet_contents);
-            return (Packet::Operator(packet_header, subpackets), remaining_bits);
+fn dump(args: &Args) -> Result<(), CliError> {
+    let packet = read_packet(args)?;
+    match args.format.as_deref().unwrap_or("sexpr") {
+        "sexpr" => println!("{}", to_sexpr(&packet, args.versions)),
+        "infix" => println!("{}", to_infix(&packet, args.versions)),
+        "dot" => print!("{}", to_dot(&packet)),
+        #[cfg(feature = "serde")]
+        "json" => println!("{}", packet_to_json(&packet)),
+        #[cfg(not(feature = "serde"))]
+        "json" => {
+            return Err(CliError::unavailable(
+                "JSON output needs d16 built with the serde feature",
+            ))
         }
+        format => return Err(CliError::usage(format!("unknown format {}", format))),
     }
+    Ok(())
 }
 
-fn decode_hex(input: &str) -> Vec<u8> {
-    match hex::decode(input) {
-        Ok(bits) => bits,
-        Err(_e) => panic!("unable to decode hex"),
+fn compile_options(args: &Args) -> Result<CompileOptions, CliError> {
+    let mut options = CompileOptions::default();
+    match args.version.as_deref() {
+        None => {}
+        Some("cycle") => options.versions = VersionStrategy::Cycle,
+        Some(version) => {
+            let version = version
+                .parse()
+                .ok()
+                .filter(|version| *version < 1 << PACKET_VERSION_SIZE)
+                .ok_or_else(|| CliError::usage(format!("invalid version {}", version)))?;
+            options.versions = VersionStrategy::Fixed(version);
+        }
     }
+    options.length_modes = match args.length.as_deref() {
+        None | Some("total") | Some("smallest") => {
+            LengthModeStrategy::Fixed(LengthMode::TotalLengthInBits)
+        }
+        Some("count") => LengthModeStrategy::Fixed(LengthMode::NumberOfSubpackets),
+        Some("alternate") => LengthModeStrategy::Alternate,
+        Some(length) => return Err(CliError::usage(format!("unknown length mode {}", length))),
+    };
+    Ok(options)
 }
 
-fn decode_packet_from_hex(input: &str) -> (Packet, BitVec<Msb0>) {
-    let decoded = decode_hex(input);
-    let bits = (&decoded).view_bits::<Msb0>();
-    let (packet, remaining_bits) = decode_packet(bits);
-    let remaining_bits_bv = remaining_bits.iter().collect();
-    (packet, remaining_bits_bv)
-}
+fn encode(args: &Args) -> Result<(), CliError> {
+    let smallest = args.length.as_deref() == Some("smallest");
+    if args.original.is_some() && !smallest {
+        return Err(CliError::usage("--original needs --length smallest"));
+    }
+    let input = read_input(args)?;
+    let input = String::from_utf8(input).map_err(CliError::failed)?;
+    let options = compile_options(args)?;
+
+    let packet = if args.json {
+        #[cfg(feature = "serde")]
+        {
+            packet_from_json(&input).map_err(CliError::failed)?
+        }
+        #[cfg(not(feature = "serde"))]
+        return Err(CliError::unavailable(
+            "JSON input needs d16 built with the serde feature",
+        ));
+    } else {
+        compile_expression(input.trim(), &options).map_err(CliError::failed)?
+    };
 
-fn compute_version_sum(packet: &Packet) -> u32 {
-    match packet {
-        Packet::Literal(packet_header, _literal) => packet_header.packet_version.into(),
-        Packet::Operator(packet_header, packets) => {
-            let packet_version_sum: u32 = packet_header.packet_version.into();
-            let subpacket_version_sums: u32 = packets
-                .into_iter()
-                .map(|packet| compute_version_sum(packet))
-                .sum();
-            packet_version_sum + subpacket_version_sums
+    // the savings go to stderr so that stdout is only the hex
+    let bits = match (&args.original, smallest) {
+        (Some(original), _) => {
+            let original = read_packet_from(args, Some(original))?;
+            let (bits, savings) =
+                encode_packet_smallest(&packet, &original).map_err(CliError::failed)?;
+            eprintln!("{}", savings);
+            bits
         }
-    }
+        (None, true) => {
+            encode_packet_with(&packet, LengthStrategy::Smallest).map_err(CliError::failed)?
+        }
+        (None, false) => {
+            encode_packet_with(&packet, LengthStrategy::Preserve).map_err(CliError::failed)?
+        }
+    };
+    println!("{}", encode_bits_to_hex(&bits));
+    Ok(())
 }
 
-fn compute_packet_result(packet: &Packet) -> u64 {
-    match packet {
-        Packet::Literal(_packet_header, literal) => *literal,
-        Packet::Operator(packet_header, packets) => {
-            let subpacket_results: Vec<u64> =
-                packets.into_iter().map(compute_packet_result).collect();
-
-            let result: u64 = match FromPrimitive::from_u8(packet_header.packet_type) {
-                Some(PacketOperatorType::Sum) => subpacket_results.into_iter().sum(),
-                Some(PacketOperatorType::Product) => subpacket_results
-                    .into_iter()
-                    .reduce(|acc, x| acc * x)
-                    .expect("Product failed"),
-                Some(PacketOperatorType::Minimum) => subpacket_results
-                    .into_iter()
-                    .reduce(|acc, x| if acc <= x { acc } else { x })
-                    .expect("Minimum failed"),
-                Some(PacketOperatorType::Maximum) => subpacket_results
-                    .into_iter()
-                    .reduce(|acc, x| if acc >= x { acc } else { x })
-                    .expect("Maximum failed"),
-                Some(PacketOperatorType::GreaterThan) => {
-                    assert!(subpacket_results.len() == 2);
-                    let result = subpacket_results[0] > subpacket_results[1];
-                    if result {
-                        1
-                    } else {
-                        0
-                    }
+fn run(args: Args) -> Result<(), CliError> {
+    match args.command.as_str() {
+        "decode" => println!("{:#}", read_packet(&args)?),
+        "eval" => {
+            let (packet, offsets) = read_packet_with_offsets_from(&args, args.file.as_deref())?;
+            if args.explain {
+                let explanation = explain_with_offsets(&packet, &offsets, Exact::default())
+                    .expect("offsets were decoded along with the packet");
+                for step in &explanation.steps {
+                    println!("{}{}", "  ".repeat(step.path.len()), step);
                 }
-                Some(PacketOperatorType::LessThan) => {
-                    assert!(subpacket_results.len() == 2);
-                    let result = subpacket_results[0] < subpacket_results[1];
-                    if result {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                Some(PacketOperatorType::EqualTo) => {
-                    assert!(subpacket_results.len() == 2);
-                    let result = subpacket_results[0] == subpacket_results[1];
-                    if result {
-                        1
-                    } else {
-                        0
-                    }
-                }
-                None => panic!("unxpected operator type found"),
+                let result = explanation.result.map_err(CliError::failed)?;
+                println!("result {}", result);
+            } else {
+                let result = compute_packet_result(&packet).map_err(CliError::failed)?;
+                println!("{}", result);
+            }
+        }
+        "versions" => println!("{}", compute_version_sum(&read_packet(&args)?)),
+        "disasm" => {
+            let bits = read_bits(&args)?;
+            let disassembly = disassemble_with(&bits, &decode_options(&args));
+            for field in &disassembly.fields {
+                println!("{}", field);
+            }
+            if let Some(e) = disassembly.error {
+                return Err(CliError::failed(e));
+            }
+        }
+        "stats" => {
+            let bits = read_bits(&args)?;
+            let (packet, _offsets) = decode_bits(&args, &bits)?;
+            print!("{}", packet_stats(&packet, bits.len()));
+        }
+        "dump" => dump(&args)?,
+        "encode" => encode(&args)?,
+        "query" => {
+            let query = args
+                .query
+                .as_deref()
+                .ok_or_else(|| CliError::usage("query needs a QUERY"))?;
+            let query = Query::parse(query).map_err(CliError::invalid)?;
+            let (packet, offsets) = read_packet_with_offsets_from(&args, args.file.as_deref())?;
+            let selected = query
+                .select_with_offsets(&packet, &offsets)
+                .expect("offsets were decoded along with the packet");
+            for selected in selected {
+                println!("{}", selected);
+            }
+        }
+        "diff" => {
+            let (old_file, new_file) = match (&args.hex, &args.file, &args.other_file) {
+                (None, Some(old_file), Some(new_file)) => (old_file, new_file),
+                _ => return Err(CliError::usage("diff needs the files OLD and NEW")),
             };
-
-            result
+            let old = read_packet_from(&args, Some(old_file))?;
+            let new = read_packet_from(&args, Some(new_file))?;
+            print!("{}", diff_packets(&old, &new));
         }
+        command => return Err(CliError::usage(format!("unknown command {}", command))),
     }
+    Ok(())
 }
 
-#[test]
-fn test() {
-    let inputs = [
-        "D2FE28",
-        "38006F45291200",
-        "EE00D40C823060",
-        "8A004A801A8002F478",
-        "620080001611562C8802118E34",
-        "C0015000016115A2E0802F182340",
-        "A0016C880162017C3686B18A3D4780",
-        "C200B40A82",
-        "04005AC33890",
-        "880086C3E88112",
-        "CE00C43D881120",
-        "D8005AC2A8F0",
-        "F600BC2D8F",
-        "9C005AC2F8F0",
-        "9C0141080250320F1802104A08",
-    ];
-
-    for input in inputs {
-        let (packet, _remaining_bits) = decode_packet_from_hex(input);
-        println!("Packet version sum {}", compute_version_sum(&packet));
-        println!("Packet result {}", compute_packet_result(&packet));
+fn main() {
+    let result = parse_args(env::args().skip(1)).and_then(run);
+    match result {
+        Ok(()) => {}
+        Err(e) if e.code == 0 => println!("{}", e.message),
+        Err(e) => {
+            eprintln!("d16: {}", e.message);
+            process::exit(e.code);
+        }
     }
 }
 
-fn read_input() -> String {
-    fs::read_to_string("input.txt")
-        .expect("unable to read input")
-        .trim()
-        .to_string()
+#[test]
+fn test_recover_checks_padding() {
+    let read = |args: &[&str]| {
+        let args = parse_args(args.iter().map(|arg| arg.to_string()))
+            .ok()
+            .unwrap();
+        read_packet(&args).map_err(|e| e.message)
+    };
+    assert!(read(&["dump", "--recover", "--hex", "D2FE29FFFF"]).is_ok());
+    assert_eq!(
+        read(&["dump", "--recover", "--strict", "--hex", "D2FE29FFFF"]).unwrap_err(),
+        "19 bits of padding after packet at bit 21 (path /)"
+    );
 }
 
-fn main() {
-    // let input = "D2FE28";
-    // let input = "38006F45291200";
-    // let input = "EE00D40C823060";
-    // let input = "8A004A801A8002F478";
-    // let input = "620080001611562C8802118E34";
-    // let input = "C0015000016115A2E0802F182340";
-    // let input = "A0016C880162017C3686B18A3D4780";
-    let input = &read_input();
-    println!("{}", input);
-
-    let (packet, _remaining_bits) = decode_packet_from_hex(input);
-    println!("Packet version sum {}", compute_version_sum(&packet));
-    println!("Packet result {}", compute_packet_result(&packet));
+#[test]
+fn test_query_syntax_error() {
+    let args = ["query", "--hex", "D2FE28", "//literal[value"];
+    let args = parse_args(args.iter().map(|arg| arg.to_string()))
+        .ok()
+        .unwrap();
+    let error = run(args).err().unwrap();
+    assert_eq!(error.message, "unexpected end of query at offset 15");
+    assert_eq!(error.code, 2);
 }
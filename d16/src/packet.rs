@@ -0,0 +1,551 @@
+use crate::decode::DecodeErrorKind;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use bitvec::prelude::*;
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
+
+pub const MIN_PACKET_SIZE: usize = 6;
+pub const PACKET_VERSION_SIZE: usize = 3;
+pub const PACKET_TYPE_SIZE: usize = 3;
+pub const LITERAL_GROUP_SIZE: usize = 4;
+pub const TOTAL_LENGTH_IN_BITS_SIZE: usize = 15;
+pub const NUMBER_OF_SUBPACKETS_SIZE: usize = 11;
+
+// without the bigint feature, literals that do not fit in 64 bits are
+// rejected by the decoder
+#[cfg(not(feature = "bigint"))]
+pub type Literal = u64;
+#[cfg(feature = "bigint")]
+pub type Literal = num_bigint::BigUint;
+
+pub type D16BitSlice = BitSlice<Msb0, u8>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "crate::json::HeaderRepr", into = "crate::json::HeaderRepr")
+)]
+pub struct PacketHeader {
+    pub packet_version: u8,
+    pub packet_type: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum LengthMode {
+    TotalLengthInBits,
+    NumberOfSubpackets,
+}
+
+// the length type ID and raw length field of an operator exactly as they were
+// read, which may disagree with the subpackets when decoding leniently
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum OperatorLength {
+    TotalLengthInBits(usize),
+    NumberOfSubpackets(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize),
+    serde(try_from = "crate::json::PacketRepr")
+)]
+pub enum Packet {
+    // the literal and the number of groups it was sent with, which is more
+    // than literal_groups when it was sent with leading zero groups
+    Literal(PacketHeader, Literal, usize),
+    Operator(PacketHeader, OperatorLength, Vec<Packet>),
+    // bits that could not be decoded, only produced when decoding with
+    // recovery, with bit_range absolute from the start of the transmission
+    Invalid {
+        bit_range: Range<usize>,
+        reason: DecodeErrorKind,
+    },
+}
+
+// Where each packet of a tree starts, in bits from the start of the
+// transmission or stream it was decoded from. Packets are numbered in the
+// order they begin, which is the order PacketFold::pre visits them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketOffsets {
+    starts: Vec<usize>,
+    // how many packets each packet holds, itself included
+    sizes: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LengthMismatch {
+    pub path: Vec<usize>,
+    pub declared: OperatorLength,
+    pub consumed: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum PacketType {
+    Literal = 4,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+pub enum PacketOperatorType {
+    Sum = 0,
+    Product,
+    Minimum,
+    Maximum,
+    GreaterThan = 5,
+    LessThan,
+    EqualTo,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    AtLeast(usize),
+    Exactly(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, nsubpackets: usize) -> bool {
+        match self {
+            Arity::AtLeast(n) => nsubpackets >= *n,
+            Arity::Exactly(n) => nsubpackets == *n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+            Arity::Exactly(n) => write!(f, "exactly {}", n),
+        }
+    }
+}
+
+impl PacketOperatorType {
+    pub fn arity(&self) -> Arity {
+        match self {
+            PacketOperatorType::Sum
+            | PacketOperatorType::Product
+            | PacketOperatorType::Minimum
+            | PacketOperatorType::Maximum => Arity::AtLeast(1),
+            PacketOperatorType::GreaterThan
+            | PacketOperatorType::LessThan
+            | PacketOperatorType::EqualTo => Arity::Exactly(2),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PacketOperatorType::Sum => "sum",
+            PacketOperatorType::Product => "product",
+            PacketOperatorType::Minimum => "minimum",
+            PacketOperatorType::Maximum => "maximum",
+            PacketOperatorType::GreaterThan => "greater_than",
+            PacketOperatorType::LessThan => "less_than",
+            PacketOperatorType::EqualTo => "equal_to",
+        }
+    }
+
+    // how the operator is written in infix expressions, either between its
+    // operands or as a call
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            PacketOperatorType::Sum => "+",
+            PacketOperatorType::Product => "*",
+            PacketOperatorType::Minimum => "min",
+            PacketOperatorType::Maximum => "max",
+            PacketOperatorType::GreaterThan => ">",
+            PacketOperatorType::LessThan => "<",
+            PacketOperatorType::EqualTo => "==",
+        }
+    }
+
+    pub fn is_infix(&self) -> bool {
+        !matches!(
+            self,
+            PacketOperatorType::Minimum | PacketOperatorType::Maximum
+        )
+    }
+}
+
+impl PacketHeader {
+    pub fn type_name(&self) -> String {
+        match FromPrimitive::from_u8(self.packet_type) {
+            Some(PacketType::Literal) => "literal".to_string(),
+            None => match PacketOperatorType::from_u8(self.packet_type) {
+                Some(operator_type) => operator_type.name().to_string(),
+                None => format!("type_{}", self.packet_type),
+            },
+        }
+    }
+}
+
+// the packet type a name from type_name stands for
+pub fn packet_type_from_name(name: &str) -> Option<u8> {
+    if name == "literal" {
+        return Some(PacketType::Literal as u8);
+    }
+    if let Some(number) = name.strip_prefix("type_") {
+        // only types that fit in the type ID field
+        let packet_type = number.parse::<u8>().ok()?;
+        return Some(packet_type).filter(|packet_type| *packet_type < 1 << PACKET_TYPE_SIZE);
+    }
+    (0..1 << PACKET_TYPE_SIZE)
+        .filter_map(PacketOperatorType::from_u8)
+        .find(|operator_type| operator_type.name() == name)
+        .map(|operator_type| operator_type as u8)
+}
+
+// paths are the subpacket index at each nesting level, written like /0/2
+pub fn format_path(path: &[usize]) -> String {
+    let path: Vec<String> = path.iter().map(|i| i.to_string()).collect();
+    format!("/{}", path.join("/"))
+}
+
+impl OperatorLength {
+    pub fn mode(&self) -> LengthMode {
+        match self {
+            OperatorLength::TotalLengthInBits(_) => LengthMode::TotalLengthInBits,
+            OperatorLength::NumberOfSubpackets(_) => LengthMode::NumberOfSubpackets,
+        }
+    }
+
+    pub fn value(&self) -> usize {
+        match self {
+            OperatorLength::TotalLengthInBits(value)
+            | OperatorLength::NumberOfSubpackets(value) => *value,
+        }
+    }
+
+    pub fn field_size(&self) -> usize {
+        match self.mode() {
+            LengthMode::TotalLengthInBits => TOTAL_LENGTH_IN_BITS_SIZE,
+            LengthMode::NumberOfSubpackets => NUMBER_OF_SUBPACKETS_SIZE,
+        }
+    }
+}
+
+#[cfg(not(feature = "bigint"))]
+fn literal_bits(literal: &Literal) -> usize {
+    (Literal::BITS - literal.leading_zeros()) as usize
+}
+
+#[cfg(feature = "bigint")]
+fn literal_bits(literal: &Literal) -> usize {
+    literal.bits() as usize
+}
+
+pub fn literal_groups(literal: &Literal) -> usize {
+    literal_bits(literal).div_ceil(LITERAL_GROUP_SIZE).max(1)
+}
+
+// the ith group of LITERAL_GROUP_SIZE bits, counting from the least
+// significant end
+pub fn literal_group(literal: &Literal, i: usize) -> u8 {
+    (0..LITERAL_GROUP_SIZE).rev().fold(0, |acc, bit| {
+        let shift = i * LITERAL_GROUP_SIZE + bit;
+        #[cfg(not(feature = "bigint"))]
+        let set = shift < Literal::BITS as usize && (literal >> shift) & 1 == 1;
+        #[cfg(feature = "bigint")]
+        let set = literal.bit(shift as u64);
+        acc << 1 | set as u8
+    })
+}
+
+impl Packet {
+    pub fn literal(packet_version: u8, literal: impl Into<Literal>) -> Packet {
+        let packet_header = PacketHeader {
+            packet_version,
+            packet_type: PacketType::Literal as u8,
+        };
+        let literal = literal.into();
+        let ngroups = literal_groups(&literal);
+        Packet::Literal(packet_header, literal, ngroups)
+    }
+
+    // fills in the length field that mode needs to describe subpackets exactly
+    pub fn operator(
+        packet_version: u8,
+        operator_type: PacketOperatorType,
+        mode: LengthMode,
+        subpackets: Vec<Packet>,
+    ) -> Packet {
+        let packet_header = PacketHeader {
+            packet_version,
+            packet_type: operator_type as u8,
+        };
+        let length = match mode {
+            LengthMode::TotalLengthInBits => {
+                OperatorLength::TotalLengthInBits(subpackets.iter().map(Packet::bit_len).sum())
+            }
+            LengthMode::NumberOfSubpackets => OperatorLength::NumberOfSubpackets(subpackets.len()),
+        };
+        Packet::Operator(packet_header, length, subpackets)
+    }
+
+    // None for invalid packets, whose header could not be read
+    pub fn header(&self) -> Option<&PacketHeader> {
+        match self {
+            Packet::Literal(packet_header, ..) | Packet::Operator(packet_header, ..) => {
+                Some(packet_header)
+            }
+            Packet::Invalid { .. } => None,
+        }
+    }
+
+    pub fn type_name(&self) -> String {
+        match self.header() {
+            Some(packet_header) => packet_header.type_name(),
+            None => "invalid".to_string(),
+        }
+    }
+
+    pub fn subpackets(&self) -> &[Packet] {
+        match self {
+            Packet::Literal(..) | Packet::Invalid { .. } => &[],
+            Packet::Operator(_packet_header, _length, subpackets) => subpackets,
+        }
+    }
+
+    // number of bits the packet occupies when encoded, a total length field
+    // counts for its declared value even if the subpackets use fewer bits
+    pub fn bit_len(&self) -> usize {
+        match self {
+            Packet::Operator(_packet_header, OperatorLength::NumberOfSubpackets(_), _) => {
+                fold_iterative(self, &mut BitLen)
+            }
+            packet => bit_len_from(packet, 0),
+        }
+    }
+
+    // operators whose length field disagrees with what their subpackets use
+    pub fn length_mismatches(&self) -> Vec<LengthMismatch> {
+        let mut collector = LengthMismatches {
+            mismatches: Vec::new(),
+            starts: Vec::new(),
+        };
+        fold_iterative(self, &mut collector);
+        collector.mismatches
+    }
+}
+
+// the bit length of packet given the bit lengths of its subpackets, which
+// only count under a subpacket count
+pub(crate) fn bit_len_from(packet: &Packet, subpacket_bits: usize) -> usize {
+    const HEADER_SIZE: usize = PACKET_VERSION_SIZE + PACKET_TYPE_SIZE;
+    match packet {
+        Packet::Literal(_packet_header, _literal, ngroups) => {
+            HEADER_SIZE + ngroups * (LITERAL_GROUP_SIZE + 1)
+        }
+        Packet::Operator(_packet_header, length, _subpackets) => {
+            let contents = match length {
+                OperatorLength::TotalLengthInBits(total_length) => *total_length,
+                OperatorLength::NumberOfSubpackets(_) => subpacket_bits,
+            };
+            HEADER_SIZE + 1 + length.field_size() + contents
+        }
+        Packet::Invalid { bit_range, .. } => bit_range.len(),
+    }
+}
+
+struct BitLen;
+
+impl PacketFold for BitLen {
+    type Output = usize;
+
+    fn post(&mut self, packet: &Packet, _context: &VisitContext, bit_lens: Vec<usize>) -> usize {
+        bit_len_from(packet, bit_lens.iter().sum())
+    }
+}
+
+// the bit length and size of every packet, numbered as in PacketOffsets
+struct Shape {
+    bit_lens: Vec<usize>,
+    sizes: Vec<usize>,
+    indices: Vec<usize>,
+}
+
+impl Shape {
+    fn of(packet: &Packet) -> Shape {
+        let mut shape = Shape {
+            bit_lens: Vec::new(),
+            sizes: Vec::new(),
+            indices: Vec::new(),
+        };
+        fold_iterative(packet, &mut shape);
+        shape
+    }
+}
+
+impl PacketFold for Shape {
+    type Output = (usize, usize);
+
+    fn pre(&mut self, _packet: &Packet, _context: &VisitContext) {
+        self.indices.push(self.sizes.len());
+        self.bit_lens.push(0);
+        self.sizes.push(0);
+    }
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        _context: &VisitContext,
+        subpackets: Vec<(usize, usize)>,
+    ) -> (usize, usize) {
+        let index = self.indices.pop().expect("pre pushed an index");
+        let bit_len = bit_len_from(packet, subpackets.iter().map(|(bits, _)| bits).sum());
+        let size = 1 + subpackets.iter().map(|(_, size)| size).sum::<usize>();
+        self.bit_lens[index] = bit_len;
+        self.sizes[index] = size;
+        (bit_len, size)
+    }
+}
+
+impl PacketOffsets {
+    // starts as the decoder recorded them, in the order the packets began
+    pub(crate) fn from_starts(packet: &Packet, starts: Vec<usize>) -> PacketOffsets {
+        PacketOffsets {
+            starts,
+            sizes: Shape::of(packet).sizes,
+        }
+    }
+
+    // where the packets would be if packet was encoded from bit start with
+    // LengthStrategy::Preserve, for trees that were not decoded
+    pub fn from_layout(packet: &Packet, start: usize) -> PacketOffsets {
+        let shape = Shape::of(packet);
+        let mut layout = Layout {
+            start,
+            bit_lens: shape.bit_lens,
+            starts: Vec::new(),
+            next: Vec::new(),
+        };
+        fold_iterative(packet, &mut layout);
+        PacketOffsets {
+            starts: layout.starts,
+            sizes: shape.sizes,
+        }
+    }
+
+    // whether these are offsets for a tree of packet's shape, as they are
+    // when decoded along with it or laid out from it
+    pub fn fit(&self, packet: &Packet) -> bool {
+        self.starts.len() == self.sizes.len() && self.sizes == Shape::of(packet).sizes
+    }
+
+    // the start of the packet numbered index
+    pub fn nth(&self, index: usize) -> Option<usize> {
+        self.starts.get(index).copied()
+    }
+
+    // subpackets are numbered from one past their operator, and each one
+    // after the packets the one before it holds
+    pub(crate) fn next_sibling(&self, index: usize) -> usize {
+        index + self.sizes[index]
+    }
+
+    pub fn get(&self, path: &[usize]) -> Option<usize> {
+        let mut index = 0;
+        for &i in path {
+            let end = index + *self.sizes.get(index)?;
+            index += 1;
+            for _ in 0..i {
+                if index >= end {
+                    return None;
+                }
+                index = self.next_sibling(index);
+            }
+            if index >= end {
+                return None;
+            }
+        }
+        self.nth(index)
+    }
+}
+
+struct Layout {
+    start: usize,
+    bit_lens: Vec<usize>,
+    starts: Vec<usize>,
+    // where the next subpacket of each operator being visited goes
+    next: Vec<usize>,
+}
+
+impl PacketFold for Layout {
+    type Output = ();
+
+    fn pre(&mut self, packet: &Packet, _context: &VisitContext) {
+        let index = self.starts.len();
+        let start = match self.next.last_mut() {
+            Some(next) => {
+                let start = *next;
+                *next += self.bit_lens[index];
+                start
+            }
+            None => self.start,
+        };
+        self.starts.push(start);
+        let header = match packet {
+            Packet::Operator(_packet_header, length, _) => {
+                PACKET_VERSION_SIZE + PACKET_TYPE_SIZE + 1 + length.field_size()
+            }
+            Packet::Literal(..) | Packet::Invalid { .. } => 0,
+        };
+        self.next.push(start + header);
+    }
+
+    fn post(&mut self, _packet: &Packet, _context: &VisitContext, _subpackets: Vec<()>) {
+        self.next.pop();
+    }
+}
+
+// works out each packet's bit length on the way up, so that nothing recurses,
+// and inserts an operator's mismatch before those of its subpackets
+struct LengthMismatches {
+    mismatches: Vec<LengthMismatch>,
+    starts: Vec<usize>,
+}
+
+impl PacketFold for LengthMismatches {
+    type Output = usize;
+
+    fn pre(&mut self, _packet: &Packet, _context: &VisitContext) {
+        self.starts.push(self.mismatches.len());
+    }
+
+    fn post(&mut self, packet: &Packet, context: &VisitContext, bit_lens: Vec<usize>) -> usize {
+        let start = self.starts.pop().unwrap();
+        let (length, subpackets) = match packet {
+            Packet::Operator(_packet_header, length, subpackets) => (length, subpackets),
+            Packet::Literal(..) | Packet::Invalid { .. } => return bit_len_from(packet, 0),
+        };
+        let subpacket_bits: usize = bit_lens.iter().sum();
+        let consumed = match length {
+            OperatorLength::TotalLengthInBits(_) => subpacket_bits,
+            OperatorLength::NumberOfSubpackets(_) => subpackets.len(),
+        };
+        if consumed != length.value() {
+            let mismatch = LengthMismatch {
+                path: context.path.to_vec(),
+                declared: *length,
+                consumed,
+            };
+            self.mismatches.insert(start, mismatch);
+        }
+        bit_len_from(packet, subpacket_bits)
+    }
+}
@@ -0,0 +1,459 @@
+use crate::packet::*;
+use std::error::Error;
+use std::fmt;
+
+// Queries select packets by walking steps from the outermost packet, much like
+// paths: /N is subpacket N, /name is any subpacket of that type and /* any
+// subpacket, while //test also looks at the packet itself and everything below
+// it. Every step can be narrowed by predicates such as [version=3] or
+// [value>=10], value only matching literals. A lone / selects the outermost
+// packet, so //product[version=3]/literal finds the literal operands of every
+// version 3 product and /0/2/* the subpackets of /0/2. //invalid finds the
+// packets that a recovering decode could not read.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryErrorKind {
+    UnexpectedCharacter(char),
+    UnexpectedEnd,
+    UnknownPacketType(String),
+    UnknownPredicate(String),
+    InvalidNumber(String),
+}
+
+// offset is the byte offset into the query where the problem starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    pub kind: QueryErrorKind,
+    pub offset: usize,
+}
+
+impl fmt::Display for QueryErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryErrorKind::UnexpectedCharacter(c) => write!(f, "unexpected character {:?}", c),
+            QueryErrorKind::UnexpectedEnd => write!(f, "unexpected end of query"),
+            QueryErrorKind::UnknownPacketType(name) => write!(f, "unknown packet type {:?}", name),
+            QueryErrorKind::UnknownPredicate(name) => write!(f, "unknown predicate {:?}", name),
+            QueryErrorKind::InvalidNumber(number) => write!(f, "invalid number {:?}", number),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at offset {}", self.kind, self.offset)
+    }
+}
+
+impl Error for QueryError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Test {
+    Any,
+    Index(usize),
+    Type(u8),
+    Invalid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds<T: PartialOrd>(&self, a: &T, b: &T) -> bool {
+        match self {
+            Comparison::Equal => a == b,
+            Comparison::NotEqual => a != b,
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Greater => a > b,
+            Comparison::GreaterOrEqual => a >= b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    Version(Comparison, u8),
+    Value(Comparison, Literal),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    descendants: bool,
+    test: Test,
+    predicates: Vec<Predicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryMatch<'a> {
+    pub path: Vec<usize>,
+    pub bit_offset: usize,
+    pub packet: &'a Packet,
+}
+
+impl fmt::Display for QueryMatch<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at bit {}: {}",
+            format_path(&self.path),
+            self.bit_offset,
+            self.packet
+        )
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    offset: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.input[self.offset..].chars().next()
+    }
+
+    fn error(&self, kind: QueryErrorKind, offset: usize) -> QueryError {
+        QueryError { kind, offset }
+    }
+
+    fn unexpected(&self) -> QueryError {
+        match self.peek() {
+            Some(c) => self.error(QueryErrorKind::UnexpectedCharacter(c), self.offset),
+            None => self.error(QueryErrorKind::UnexpectedEnd, self.offset),
+        }
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.offset += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &str {
+        let start = self.offset;
+        while let Some(c) = self.peek().filter(|c| f(*c)) {
+            self.offset += c.len_utf8();
+        }
+        &self.input[start..self.offset]
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, QueryError> {
+        let start = self.offset;
+        let digits = self.take_while(|c| c.is_ascii_digit()).to_string();
+        if digits.is_empty() {
+            return Err(self.unexpected());
+        }
+        digits
+            .parse()
+            .map_err(|_| self.error(QueryErrorKind::InvalidNumber(digits), start))
+    }
+
+    fn test(&mut self) -> Result<Test, QueryError> {
+        let start = self.offset;
+        match self.peek() {
+            Some('*') => {
+                self.offset += 1;
+                Ok(Test::Any)
+            }
+            Some(c) if c.is_ascii_digit() => Ok(Test::Index(self.number()?)),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let name = self
+                    .take_while(|c| c.is_ascii_alphanumeric() || c == '_')
+                    .to_string();
+                match packet_type_from_name(&name) {
+                    _ if name == "invalid" => Ok(Test::Invalid),
+                    Some(packet_type) => Ok(Test::Type(packet_type)),
+                    None => Err(self.error(QueryErrorKind::UnknownPacketType(name), start)),
+                }
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Comparison, QueryError> {
+        let comparison = if self.eat('=') {
+            Comparison::Equal
+        } else if self.eat('!') {
+            if !self.eat('=') {
+                return Err(self.unexpected());
+            }
+            Comparison::NotEqual
+        } else if self.eat('<') {
+            match self.eat('=') {
+                true => Comparison::LessOrEqual,
+                false => Comparison::Less,
+            }
+        } else if self.eat('>') {
+            match self.eat('=') {
+                true => Comparison::GreaterOrEqual,
+                false => Comparison::Greater,
+            }
+        } else {
+            return Err(self.unexpected());
+        };
+        Ok(comparison)
+    }
+
+    fn predicate(&mut self) -> Result<Predicate, QueryError> {
+        let start = self.offset;
+        let name = self.take_while(|c| c.is_ascii_alphabetic()).to_string();
+        let comparison = match name.as_str() {
+            "version" | "value" => self.comparison()?,
+            "" => return Err(self.unexpected()),
+            _ => return Err(self.error(QueryErrorKind::UnknownPredicate(name), start)),
+        };
+        let predicate = match name.as_str() {
+            "version" => Predicate::Version(comparison, self.number()?),
+            _ => Predicate::Value(comparison, self.number()?),
+        };
+        if !self.eat(']') {
+            return Err(self.unexpected());
+        }
+        Ok(predicate)
+    }
+
+    fn step(&mut self) -> Result<Step, QueryError> {
+        let descendants = self.eat('/');
+        let test = self.test()?;
+        let mut predicates = Vec::new();
+        while self.eat('[') {
+            predicates.push(self.predicate()?);
+        }
+        Ok(Step {
+            descendants,
+            test,
+            predicates,
+        })
+    }
+}
+
+impl Step {
+    fn accepts(&self, index: Option<usize>, packet: &Packet) -> bool {
+        let test = match self.test {
+            Test::Any => true,
+            Test::Index(i) => index == Some(i),
+            Test::Type(packet_type) => packet.header().map(|h| h.packet_type) == Some(packet_type),
+            Test::Invalid => matches!(packet, Packet::Invalid { .. }),
+        };
+        test && self
+            .predicates
+            .iter()
+            .all(|predicate| match (predicate, packet) {
+                (Predicate::Version(comparison, version), _) => packet
+                    .header()
+                    .is_some_and(|h| comparison.holds(&h.packet_version, version)),
+                (Predicate::Value(comparison, value), Packet::Literal(_, literal, _)) => {
+                    comparison.holds(literal, value)
+                }
+                (Predicate::Value(..), _) => false,
+            })
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Query, QueryError> {
+        let mut parser = Parser { input, offset: 0 };
+        let mut steps = Vec::new();
+        if !parser.eat('/') {
+            return Err(parser.unexpected());
+        }
+        if parser.peek().is_some() {
+            steps.push(parser.step()?);
+            while parser.eat('/') {
+                steps.push(parser.step()?);
+            }
+            if parser.peek().is_some() {
+                return Err(parser.unexpected());
+            }
+        }
+        Ok(Query { steps })
+    }
+
+    // matches in the order the packets appear in the transmission, at bit
+    // offsets as though packet was encoded from bit 0
+    pub fn select<'a>(&self, packet: &'a Packet) -> Vec<QueryMatch<'a>> {
+        self.select_at(packet, &PacketOffsets::from_layout(packet, 0))
+    }
+
+    // offsets are where the packets of packet were decoded from, see
+    // decode_packet_with_offsets and StreamedPacket, and None if they are the
+    // offsets of some other tree
+    pub fn select_with_offsets<'a>(
+        &self,
+        packet: &'a Packet,
+        offsets: &PacketOffsets,
+    ) -> Option<Vec<QueryMatch<'a>>> {
+        if !offsets.fit(packet) {
+            return None;
+        }
+        Some(self.select_at(packet, offsets))
+    }
+
+    fn select_at<'a>(&self, packet: &'a Packet, offsets: &PacketOffsets) -> Vec<QueryMatch<'a>> {
+        // matches are kept with their number in offsets, which is also the
+        // order they appear in
+        let nth = |index| offsets.nth(index).expect("offsets cover every packet");
+        let mut matches = vec![(
+            0,
+            QueryMatch {
+                path: Vec::new(),
+                bit_offset: nth(0),
+                packet,
+            },
+        )];
+        for step in &self.steps {
+            let mut selected = Vec::new();
+            // packets numbered below covered are under a context that //
+            // has already walked
+            let mut covered = 0;
+            for (context_index, context) in &matches {
+                if step.descendants {
+                    if *context_index < covered {
+                        continue;
+                    }
+                    covered = offsets.next_sibling(*context_index);
+                    let index = context.path.last().copied();
+                    if step.accepts(index, context.packet) {
+                        selected.push((*context_index, context.clone()));
+                    }
+                }
+
+                // each frame is a packet whose subpackets are being walked,
+                // the next one to look at and its number
+                let mut path = context.path.clone();
+                let mut stack = vec![(context.packet, 0, context_index + 1)];
+                while let Some((parent, next_subpacket, next_index)) = stack.last_mut() {
+                    let subpacket = match parent.subpackets().get(*next_subpacket) {
+                        Some(subpacket) => subpacket,
+                        None => {
+                            stack.pop();
+                            if !stack.is_empty() {
+                                path.pop();
+                            }
+                            continue;
+                        }
+                    };
+                    let (i, index) = (*next_subpacket, *next_index);
+                    *next_subpacket += 1;
+                    *next_index = offsets.next_sibling(index);
+
+                    path.push(i);
+                    if step.accepts(Some(i), subpacket) {
+                        let child = QueryMatch {
+                            path: path.clone(),
+                            bit_offset: nth(index),
+                            packet: subpacket,
+                        };
+                        selected.push((index, child));
+                    }
+                    if step.descendants {
+                        stack.push((subpacket, 0, index + 1));
+                    } else {
+                        path.pop();
+                    }
+                }
+            }
+            // contexts can hold one another, so a step without // can select
+            // the subpackets of an inner context before those of an outer one
+            selected.sort_by_key(|(index, _)| *index);
+            matches = selected;
+        }
+        matches.into_iter().map(|(_, selected)| selected).collect()
+    }
+}
+
+pub fn select<'a>(packet: &'a Packet, query: &str) -> Result<Vec<QueryMatch<'a>>, QueryError> {
+    Ok(Query::parse(query)?.select(packet))
+}
+
+#[cfg(test)]
+use crate::compile::{compile_expression, CompileOptions, VersionStrategy};
+
+#[test]
+fn test_select() {
+    let options = CompileOptions {
+        versions: VersionStrategy::Cycle,
+        ..CompileOptions::default()
+    };
+    let packet = compile_expression("(2 * 3) + min(4, 5 * 6, 7) + 8", &options).unwrap();
+    let paths = |query: &str| -> Vec<String> {
+        select(&packet, query)
+            .unwrap()
+            .iter()
+            .map(|m| format!("{} {}", format_path(&m.path), m.bit_offset))
+            .collect()
+    };
+    assert_eq!(paths("/"), ["/ 0"]);
+    assert_eq!(paths("//sum"), ["/ 0"]);
+    assert_eq!(paths("/1/*"), ["/1/0 88", "/1/1 99", "/1/2 143"]);
+    assert_eq!(
+        paths("//product/literal"),
+        ["/0/0 44", "/0/1 55", "/1/1/0 121", "/1/1/1 132"]
+    );
+    assert_eq!(
+        paths("//literal[value>=5][value<8]"),
+        ["/1/1/0 121", "/1/1/1 132", "/1/2 143"]
+    );
+    assert_eq!(
+        paths("//*[version=6]/literal"),
+        ["/1/1/0 121", "/1/1/1 132"]
+    );
+    assert_eq!(paths("/1//literal[value=4]"), ["/1/0 88"]);
+    assert_eq!(paths("//*//*"), paths("//*"));
+    assert_eq!(paths("//product//literal"), paths("//product/literal"));
+    assert_eq!(paths("/3"), Vec::<String>::new());
+
+    let error = |query: &str| Query::parse(query).unwrap_err();
+    assert_eq!(error("sum").kind, QueryErrorKind::UnexpectedCharacter('s'));
+    assert_eq!(
+        error("//sums").kind,
+        QueryErrorKind::UnknownPacketType("sums".to_string())
+    );
+    assert_eq!(
+        error("//type_200").kind,
+        QueryErrorKind::UnknownPacketType("type_200".to_string())
+    );
+    assert_eq!(
+        error("/*[depth=2]").kind,
+        QueryErrorKind::UnknownPredicate("depth".to_string())
+    );
+    assert_eq!(error("/*[version=2").kind, QueryErrorKind::UnexpectedEnd);
+    assert_eq!(error("/*[version=2").offset, 12);
+}
+
+#[test]
+fn test_select_with_offsets() {
+    // the second packet of the stream starts at bit 24, and its second
+    // literal 33 bits into it
+    let input = "D2FE28\n38006F4529\n1200EE00D40C823060\n0000";
+    let packets = crate::decode::stream::decode_packets_from_hex(input).unwrap();
+    let query = Query::parse("//literal").unwrap();
+    let offsets: Vec<usize> = query
+        .select_with_offsets(&packets[1].packet, &packets[1].offsets)
+        .unwrap()
+        .iter()
+        .map(|m| m.bit_offset)
+        .collect();
+    assert_eq!(offsets, [24 + 22, 24 + 33]);
+
+    // offsets for a different tree do not fit
+    assert!(query
+        .select_with_offsets(&packets[2].packet, &packets[1].offsets)
+        .is_none());
+}
@@ -0,0 +1,181 @@
+use crate::packet::*;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use num_traits::FromPrimitive;
+use std::fmt;
+
+fn annotate(text: String, context: &VisitContext, versions: bool) -> String {
+    match context.header {
+        Some(packet_header) if versions => format!("{}@v{}", text, packet_header.packet_version),
+        _ => text,
+    }
+}
+
+// invalid packets render the same way in every notation
+fn invalid(bit_range: &std::ops::Range<usize>) -> String {
+    format!("<invalid bits {}..{}>", bit_range.start, bit_range.end)
+}
+
+struct SExpr {
+    versions: bool,
+}
+
+impl PacketFold for SExpr {
+    type Output = String;
+
+    fn post(&mut self, packet: &Packet, context: &VisitContext, subpackets: Vec<String>) -> String {
+        match packet {
+            Packet::Literal(_packet_header, literal, _ngroups) => {
+                annotate(literal.to_string(), context, self.versions)
+            }
+            Packet::Operator(packet_header, ..) => {
+                let name = annotate(packet_header.type_name(), context, self.versions);
+                let mut parts = vec![name];
+                parts.extend(subpackets);
+                format!("({})", parts.join(" "))
+            }
+            Packet::Invalid { bit_range, .. } => invalid(bit_range),
+        }
+    }
+}
+
+// a rendered subexpression and whether it needs parentheses when it is the
+// operand of an infix operator
+struct Rendered {
+    text: String,
+    compound: bool,
+}
+
+struct Infix {
+    versions: bool,
+}
+
+impl PacketFold for Infix {
+    type Output = Rendered;
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        context: &VisitContext,
+        subpackets: Vec<Rendered>,
+    ) -> Rendered {
+        let (text, compound) = match packet {
+            Packet::Literal(_packet_header, literal, _ngroups) => (literal.to_string(), false),
+            Packet::Operator(packet_header, ..) => {
+                let operator_type = PacketOperatorType::from_u8(packet_header.packet_type);
+                match operator_type {
+                    Some(operator_type)
+                        if operator_type.is_infix()
+                            && operator_type.arity().accepts(subpackets.len())
+                            && subpackets.len() > 1 =>
+                    {
+                        let operands: Vec<String> = subpackets
+                            .into_iter()
+                            .map(|operand| match operand.compound {
+                                true => format!("({})", operand.text),
+                                false => operand.text,
+                            })
+                            .collect();
+                        let separator = format!(" {} ", operator_type.symbol());
+                        (operands.join(&separator), true)
+                    }
+                    _ => {
+                        // min, max, unknown types and operators with a single
+                        // operand are written as calls
+                        let name = match operator_type {
+                            Some(operator_type) if !operator_type.is_infix() => {
+                                operator_type.symbol().to_string()
+                            }
+                            _ => packet_header.type_name(),
+                        };
+                        let arguments: Vec<String> =
+                            subpackets.into_iter().map(|operand| operand.text).collect();
+                        (format!("{}({})", name, arguments.join(", ")), false)
+                    }
+                }
+            }
+            Packet::Invalid { bit_range, .. } => (invalid(bit_range), false),
+        };
+
+        if self.versions && compound {
+            Rendered {
+                text: annotate(format!("({})", text), context, true),
+                compound: false,
+            }
+        } else {
+            Rendered {
+                text: annotate(text, context, self.versions),
+                compound,
+            }
+        }
+    }
+}
+
+// renders like (sum 1 (product 2 3)), or (sum@v3 1@v4 ...) with versions
+pub fn to_sexpr(packet: &Packet, versions: bool) -> String {
+    fold_iterative(packet, &mut SExpr { versions })
+}
+
+// renders like 1 + (2 * 3), with min(..) and max(..) as calls; with versions
+// every literal and operator is followed by @v and its version
+pub fn to_infix(packet: &Packet, versions: bool) -> String {
+    fold_iterative(packet, &mut Infix { versions }).text
+}
+
+impl fmt::Display for Packet {
+    // {} is the S-expression and {:#} the infix expression
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            write!(f, "{}", to_infix(self, false))
+        } else {
+            write!(f, "{}", to_sexpr(self, false))
+        }
+    }
+}
+
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+
+#[test]
+fn test_render() {
+    let packet = Packet::operator(
+        1,
+        PacketOperatorType::Sum,
+        LengthMode::NumberOfSubpackets,
+        vec![
+            Packet::literal(4, 1u8),
+            Packet::operator(
+                2,
+                PacketOperatorType::Product,
+                LengthMode::TotalLengthInBits,
+                vec![Packet::literal(5, 2u8), Packet::literal(6, 3u8)],
+            ),
+        ],
+    );
+    assert_eq!(packet.to_string(), "(sum 1 (product 2 3))");
+    assert_eq!(format!("{:#}", packet), "1 + (2 * 3)");
+    assert_eq!(
+        to_sexpr(&packet, true),
+        "(sum@v1 1@v4 (product@v2 2@v5 3@v6))"
+    );
+    assert_eq!(to_infix(&packet, true), "(1@v4 + (2@v5 * 3@v6)@v2)@v1");
+
+    let inputs = [
+        ("C200B40A82", "1 + 2"),
+        ("880086C3E88112", "min(7, 8, 9)"),
+        ("D8005AC2A8F0", "5 < 15"),
+        ("9C0141080250320F1802104A08", "(1 + 3) == (2 * 2)"),
+        ("CE00C43D881120", "max(7, 8, 9)"),
+    ];
+    for (input, expected) in inputs {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        assert_eq!(to_infix(&packet, false), expected);
+    }
+
+    let single = Packet::operator(
+        0,
+        PacketOperatorType::Sum,
+        LengthMode::NumberOfSubpackets,
+        vec![Packet::literal(0, 5u8)],
+    );
+    assert_eq!(to_infix(&single, false), "sum(5)");
+}
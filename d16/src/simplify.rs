@@ -0,0 +1,243 @@
+use crate::eval::compute_packet_result;
+use crate::packet::*;
+use crate::validate::validate;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use num_traits::FromPrimitive;
+
+// rebuilds an operator around new subpackets, keeping its length mode unless
+// there are now too many subpackets to count
+fn rebuild(packet_header: &PacketHeader, mode: LengthMode, subpackets: Vec<Packet>) -> Packet {
+    let mode = match mode {
+        LengthMode::NumberOfSubpackets if subpackets.len() >= 1 << NUMBER_OF_SUBPACKETS_SIZE => {
+            LengthMode::TotalLengthInBits
+        }
+        mode => mode,
+    };
+    let length = match mode {
+        LengthMode::TotalLengthInBits => {
+            OperatorLength::TotalLengthInBits(subpackets.iter().map(Packet::bit_len).sum())
+        }
+        LengthMode::NumberOfSubpackets => OperatorLength::NumberOfSubpackets(subpackets.len()),
+    };
+    Packet::Operator(*packet_header, length, subpackets)
+}
+
+// the value of operator_type applied to literals, or None if it overflows
+fn fold_literals(operator_type: PacketOperatorType, literals: Vec<Packet>) -> Option<Literal> {
+    let packet = Packet::operator(0, operator_type, LengthMode::NumberOfSubpackets, literals);
+    compute_packet_result(&packet).ok()
+}
+
+// whether two packets compute the same thing, ignoring versions and lengths
+fn same_expression(a: &Packet, b: &Packet) -> bool {
+    let mut stack = vec![(a, b)];
+    while let Some((a, b)) = stack.pop() {
+        match (a, b) {
+            (Packet::Literal(_, a, _), Packet::Literal(_, b, _)) if a == b => {}
+            (
+                Packet::Operator(a_header, _, a_subpackets),
+                Packet::Operator(b_header, _, b_subpackets),
+            ) if a_header.packet_type == b_header.packet_type
+                && a_subpackets.len() == b_subpackets.len() =>
+            {
+                stack.extend(a_subpackets.iter().zip(b_subpackets));
+            }
+            _ => return false,
+        }
+    }
+    true
+}
+
+struct Simplifier;
+
+impl PacketFold for Simplifier {
+    type Output = Packet;
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        _context: &VisitContext,
+        subpackets: Vec<Packet>,
+    ) -> Packet {
+        let (packet_header, mode) = match packet {
+            Packet::Literal(..) | Packet::Invalid { .. } => return packet.clone(),
+            Packet::Operator(packet_header, length, _) => (packet_header, length.mode()),
+        };
+        // packets that cannot be evaluated are left alone so that they still
+        // fail the same way
+        let operator_type = match PacketOperatorType::from_u8(packet_header.packet_type) {
+            Some(operator_type) if operator_type.arity().accepts(subpackets.len()) => operator_type,
+            _ => return rebuild(packet_header, mode, subpackets),
+        };
+        let associative = operator_type.arity() == Arity::AtLeast(1);
+
+        let mut subpackets = match operator_type {
+            PacketOperatorType::Sum | PacketOperatorType::Product => {
+                let mut flattened = Vec::with_capacity(subpackets.len());
+                for subpacket in subpackets {
+                    match subpacket {
+                        Packet::Operator(subpacket_header, _, nested)
+                            if subpacket_header.packet_type == packet_header.packet_type
+                                && !nested.is_empty() =>
+                        {
+                            flattened.extend(nested)
+                        }
+                        subpacket => flattened.push(subpacket),
+                    }
+                }
+                flattened
+            }
+            _ => subpackets,
+        };
+
+        // literal operands of sum, product, min and max can be combined
+        // wherever they are, the operators being commutative
+        let nliterals = subpackets
+            .iter()
+            .filter(|subpacket| matches!(subpacket, Packet::Literal(..)))
+            .count();
+        if associative && nliterals > 1 {
+            let first = subpackets
+                .iter()
+                .position(|subpacket| matches!(subpacket, Packet::Literal(..)))
+                .unwrap();
+            let (literals, mut others): (Vec<Packet>, Vec<Packet>) = subpackets
+                .into_iter()
+                .partition(|subpacket| matches!(subpacket, Packet::Literal(..)));
+            let literal_version = literals[0]
+                .header()
+                .expect("literals have a header")
+                .packet_version;
+            match fold_literals(operator_type, literals.clone()) {
+                Some(literal) => {
+                    others.insert(first, Packet::literal(literal_version, literal));
+                    subpackets = others;
+                }
+                None => {
+                    others.splice(first..first, literals);
+                    subpackets = others;
+                }
+            }
+        }
+
+        if subpackets.len() == 1 && associative {
+            return subpackets.pop().unwrap();
+        }
+
+        if subpackets
+            .iter()
+            .all(|subpacket| matches!(subpacket, Packet::Literal(..)))
+        {
+            if let Some(literal) = fold_literals(operator_type, subpackets.clone()) {
+                return Packet::literal(packet_header.packet_version, literal);
+            }
+        }
+
+        if !associative
+            && same_expression(&subpackets[0], &subpackets[1])
+            && validate(&subpackets[0]).is_empty()
+        {
+            let equal = operator_type == PacketOperatorType::EqualTo;
+            return Packet::literal(packet_header.packet_version, equal as u8);
+        }
+
+        rebuild(packet_header, mode, subpackets)
+    }
+}
+
+// Folds constant operators, flattens nested sums and products, unwraps sum,
+// product, min and max packets with a single operand and decides comparisons
+// of a subtree with itself. The result evaluates to the same value as packet
+// whenever packet evaluates; versions are not kept, and an overflow in the
+// original may evaluate once its operands are regrouped, such as a product
+// that overflows before reaching a zero.
+pub fn simplify(packet: &Packet) -> Packet {
+    fold_iterative(packet, &mut Simplifier)
+}
+
+#[cfg(test)]
+use crate::compile::{compile_expression, CompileOptions};
+#[cfg(test)]
+use crate::decode::decode_packet_from_hex;
+#[cfg(all(test, not(feature = "bigint")))]
+use crate::render::to_infix;
+
+#[test]
+fn test_simplify() {
+    let inputs = [
+        "C200B40A82",
+        "04005AC33890",
+        "880086C3E88112",
+        "CE00C43D881120",
+        "D8005AC2A8F0",
+        "F600BC2D8F",
+        "9C005AC2F8F0",
+        "9C0141080250320F1802104A08",
+        "8A004A801A8002F478",
+        "A0016C880162017C3686B18A3D4780",
+    ];
+    for input in inputs {
+        let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+        let simplified = simplify(&packet);
+        assert!(matches!(simplified, Packet::Literal(..)), "{}", input);
+        assert_eq!(
+            compute_packet_result(&simplified),
+            compute_packet_result(&packet)
+        );
+        assert!(simplified.bit_len() <= packet.bit_len());
+    }
+
+    // the overflowing product cannot be folded, so the rules that do not
+    // need values are left to do the work
+    let max = u64::MAX;
+    let cases = [
+        (
+            format!("1 + ({} * {} * 2) + 2", max, max),
+            format!("3 + ({} * {} * 2)", max, max),
+        ),
+        (
+            format!("1 + (2 + ({} * {}) + 3)", max, max),
+            format!("6 + ({} * {})", max, max),
+        ),
+        (
+            format!("sum({} * {})", max, max),
+            format!("{} * {}", max, max),
+        ),
+        (
+            format!("(({} * {}) == ({} * {})) + 4", max, max, max, max),
+            "5".to_string(),
+        ),
+        (
+            format!("(({} * {}) < ({} * {})) + 4", max, max, max, max),
+            "4".to_string(),
+        ),
+        (
+            format!("min(max(8 * 3, 6), {} * {}, 9)", max, max),
+            format!("min(9, {} * {})", max, max),
+        ),
+    ];
+    for (input, expected) in cases {
+        let packet = compile_expression(&input, &CompileOptions::default()).unwrap();
+        let simplified = simplify(&packet);
+        #[cfg(not(feature = "bigint"))]
+        assert_eq!(to_infix(&simplified, false), expected, "{}", input);
+        #[cfg(feature = "bigint")]
+        let _ = expected;
+        let result = compute_packet_result(&packet);
+        if result.is_ok() {
+            assert_eq!(compute_packet_result(&simplified), result);
+        }
+    }
+
+    // packets that would fail to evaluate keep failing
+    let packet_header = PacketHeader {
+        packet_version: 0,
+        packet_type: PacketOperatorType::LessThan as u8,
+    };
+    let packet = Packet::Operator(
+        packet_header,
+        OperatorLength::NumberOfSubpackets(1),
+        vec![Packet::literal(0, 1u8)],
+    );
+    assert_eq!(simplify(&packet), packet);
+}
@@ -0,0 +1,219 @@
+use crate::packet::*;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// Where the bits of a transmission go. The length type ID bit is counted with
+// the length field it selects, and bits skipped at the end of a total length
+// operator are counted as padding along with those after the outermost packet.
+// Literals are counted with the groups they were sent with, leading zero
+// groups included, and invalid holds the bits of invalid packets.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BitBudget {
+    pub headers: usize,
+    pub literal_payload: usize,
+    pub continuation: usize,
+    pub length_fields: usize,
+    pub padding: usize,
+    pub invalid: usize,
+}
+
+impl BitBudget {
+    pub fn total(&self) -> usize {
+        self.headers
+            + self.literal_payload
+            + self.continuation
+            + self.length_fields
+            + self.padding
+            + self.invalid
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+// invalid packets count towards packets and depths but have no type or version
+pub struct PacketStats {
+    pub packets: usize,
+    pub invalid_packets: usize,
+    pub by_type: BTreeMap<u8, usize>,
+    pub by_version: BTreeMap<u8, usize>,
+    // depth 0 is the outermost packet
+    pub max_depth: usize,
+    pub total_depth: usize,
+    pub literal_groups: usize,
+    pub total_length_operators: usize,
+    pub subpacket_count_operators: usize,
+    pub bits: BitBudget,
+}
+
+impl PacketStats {
+    pub fn average_depth(&self) -> f64 {
+        self.total_depth as f64 / self.packets as f64
+    }
+}
+
+struct Stats {
+    stats: PacketStats,
+}
+
+// the output is the bit length of each packet, worked out on the way up so
+// that nothing recurses
+impl PacketFold for Stats {
+    type Output = usize;
+
+    fn post(&mut self, packet: &Packet, context: &VisitContext, bit_lens: Vec<usize>) -> usize {
+        let subpacket_bits = bit_lens.iter().sum();
+        let stats = &mut self.stats;
+        stats.packets += 1;
+        stats.max_depth = stats.max_depth.max(context.depth);
+        stats.total_depth += context.depth;
+        if let Some(packet_header) = context.header {
+            *stats.by_type.entry(packet_header.packet_type).or_default() += 1;
+            *stats
+                .by_version
+                .entry(packet_header.packet_version)
+                .or_default() += 1;
+            stats.bits.headers += PACKET_VERSION_SIZE + PACKET_TYPE_SIZE;
+        }
+
+        match packet {
+            Packet::Literal(_packet_header, _literal, ngroups) => {
+                let ngroups = *ngroups;
+                stats.literal_groups += ngroups;
+                stats.bits.literal_payload += ngroups * LITERAL_GROUP_SIZE;
+                stats.bits.continuation += ngroups;
+            }
+            Packet::Operator(_packet_header, length, _subpackets) => {
+                stats.bits.length_fields += 1 + length.field_size();
+                match length {
+                    OperatorLength::TotalLengthInBits(total_length) => {
+                        stats.total_length_operators += 1;
+                        stats.bits.padding += total_length.saturating_sub(subpacket_bits);
+                    }
+                    OperatorLength::NumberOfSubpackets(_) => stats.subpacket_count_operators += 1,
+                }
+            }
+            Packet::Invalid { bit_range, .. } => {
+                stats.invalid_packets += 1;
+                stats.bits.invalid += bit_range.len();
+            }
+        }
+        bit_len_from(packet, subpacket_bits)
+    }
+}
+
+// statistics for packet as decoded from a transmission of transmission_bits
+// bits, anything beyond the packet itself counting as padding
+pub fn packet_stats(packet: &Packet, transmission_bits: usize) -> PacketStats {
+    let mut stats = Stats {
+        stats: PacketStats::default(),
+    };
+    let bit_len = fold_iterative(packet, &mut stats);
+    let mut stats = stats.stats;
+    stats.bits.padding += transmission_bits.saturating_sub(bit_len);
+    stats
+}
+
+fn percentage(part: usize, whole: usize) -> f64 {
+    100.0 * part as f64 / whole.max(1) as f64
+}
+
+impl fmt::Display for PacketStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "packets {}", self.packets)?;
+        for (packet_type, count) in &self.by_type {
+            let packet_header = PacketHeader {
+                packet_version: 0,
+                packet_type: *packet_type,
+            };
+            writeln!(f, "  {:<14} {}", packet_header.type_name(), count)?;
+        }
+        if self.invalid_packets > 0 {
+            writeln!(f, "  {:<14} {}", "invalid", self.invalid_packets)?;
+        }
+        writeln!(f, "versions")?;
+        for (packet_version, count) in &self.by_version {
+            writeln!(f, "  {:<14} {}", packet_version, count)?;
+        }
+        writeln!(
+            f,
+            "depth max {} average {:.2}",
+            self.max_depth,
+            self.average_depth()
+        )?;
+        writeln!(f, "literal groups {}", self.literal_groups)?;
+        writeln!(
+            f,
+            "length modes total length {} subpacket count {}",
+            self.total_length_operators, self.subpacket_count_operators
+        )?;
+
+        let total = self.bits.total();
+        writeln!(f, "bits {}", total)?;
+        let budget = [
+            ("headers", self.bits.headers),
+            ("literal payload", self.bits.literal_payload),
+            ("continuation", self.bits.continuation),
+            ("length fields", self.bits.length_fields),
+            ("padding", self.bits.padding),
+            ("invalid", self.bits.invalid),
+        ];
+        for (name, bits) in budget {
+            if name == "invalid" && bits == 0 {
+                continue;
+            }
+            writeln!(
+                f,
+                "  {:<16} {:>8} {:>6.1}%",
+                name,
+                bits,
+                percentage(bits, total)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+use crate::decode::{decode_packet, decode_packet_from_hex};
+
+#[test]
+fn test_packet_stats() {
+    let input = "9C0141080250320F1802104A08";
+    let (packet, _remaining_bits) = decode_packet_from_hex(input).unwrap();
+    let stats = packet_stats(&packet, input.len() * 4);
+
+    assert_eq!(stats.packets, 7);
+    assert_eq!(stats.by_type[&(PacketType::Literal as u8)], 4);
+    assert_eq!(stats.by_type[&(PacketOperatorType::EqualTo as u8)], 1);
+    assert_eq!(stats.by_version[&2], 3);
+    assert_eq!(stats.max_depth, 2);
+    assert_eq!(stats.average_depth(), 10.0 / 7.0);
+    assert_eq!(stats.literal_groups, 4);
+    assert_eq!(stats.total_length_operators, 1);
+    assert_eq!(stats.subpacket_count_operators, 2);
+    assert_eq!(
+        stats.bits,
+        BitBudget {
+            headers: 42,
+            literal_payload: 16,
+            continuation: 4,
+            length_fields: 16 + 12 + 12,
+            padding: 2,
+            invalid: 0,
+        }
+    );
+    assert_eq!(stats.bits.total(), input.len() * 4);
+
+    let report = stats.to_string();
+    assert!(report.contains("  literal        4\n"));
+    assert!(report.contains("  padding                 2    1.9%\n"));
+
+    let bits = crate::decode::padded_literal_sum();
+    let (packet, _remaining_bits) = decode_packet(&bits).unwrap();
+    let stats = packet_stats(&packet, bits.len());
+    assert_eq!(stats.literal_groups, 4);
+    assert_eq!(stats.bits.literal_payload, 16);
+    assert_eq!(stats.bits.continuation, 4);
+    assert_eq!(stats.bits.padding, 0);
+    assert_eq!(stats.bits.total(), bits.len());
+}
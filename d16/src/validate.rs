@@ -0,0 +1,129 @@
+use crate::packet::*;
+use crate::visit::{fold_iterative, PacketFold, VisitContext};
+use num_traits::FromPrimitive;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValidationIssueKind {
+    WrongArity { expected: Arity, found: usize },
+    // an operator packet with the literal type ID or one out of range, or a
+    // literal packet with an operator type ID
+    UnknownType,
+    // bits that could not be decoded, see Packet::Invalid
+    InvalidPacket,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub kind: ValidationIssueKind,
+    pub path: Vec<usize>,
+    pub type_name: String,
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = format_path(&self.path);
+        match self.kind {
+            ValidationIssueKind::WrongArity { expected, found } => write!(
+                f,
+                "{} at {} takes {} subpackets but has {}",
+                self.type_name, path, expected, found
+            ),
+            ValidationIssueKind::UnknownType => {
+                write!(f, "{} at {} has an unexpected type", self.type_name, path)
+            }
+            ValidationIssueKind::InvalidPacket => {
+                write!(f, "{} at {} could not be decoded", self.type_name, path)
+            }
+        }
+    }
+}
+
+// checks a single packet, not its subpackets
+pub fn check_packet(packet: &Packet) -> Option<ValidationIssueKind> {
+    match packet {
+        Packet::Literal(packet_header, ..) => {
+            match FromPrimitive::from_u8(packet_header.packet_type) {
+                Some(PacketType::Literal) => None,
+                None => Some(ValidationIssueKind::UnknownType),
+            }
+        }
+        Packet::Operator(packet_header, _length, subpackets) => {
+            match PacketOperatorType::from_u8(packet_header.packet_type) {
+                Some(operator_type) if !operator_type.arity().accepts(subpackets.len()) => {
+                    Some(ValidationIssueKind::WrongArity {
+                        expected: operator_type.arity(),
+                        found: subpackets.len(),
+                    })
+                }
+                Some(_operator_type) => None,
+                None => Some(ValidationIssueKind::UnknownType),
+            }
+        }
+        Packet::Invalid { .. } => Some(ValidationIssueKind::InvalidPacket),
+    }
+}
+
+struct Validator {
+    issues: Vec<ValidationIssue>,
+}
+
+// issues are collected on the way down so they come out in tree order
+impl PacketFold for Validator {
+    type Output = ();
+
+    fn pre(&mut self, packet: &Packet, context: &VisitContext) {
+        if let Some(kind) = check_packet(packet) {
+            self.issues.push(ValidationIssue {
+                kind,
+                path: context.path.to_vec(),
+                type_name: packet.type_name(),
+            });
+        }
+    }
+
+    fn post(&mut self, _packet: &Packet, _context: &VisitContext, _subpackets: Vec<()>) {}
+}
+
+pub fn validate(packet: &Packet) -> Vec<ValidationIssue> {
+    let mut validator = Validator { issues: Vec::new() };
+    fold_iterative(packet, &mut validator);
+    validator.issues
+}
+
+#[test]
+fn test_validate() {
+    let operator = |operator_type, subpackets| {
+        Packet::operator(0, operator_type, LengthMode::NumberOfSubpackets, subpackets)
+    };
+    let packet = operator(
+        PacketOperatorType::Sum,
+        vec![
+            operator(PacketOperatorType::Product, vec![]),
+            operator(PacketOperatorType::LessThan, vec![Packet::literal(0, 1u8)]),
+            operator(
+                PacketOperatorType::EqualTo,
+                vec![Packet::literal(0, 1u8), Packet::literal(0, 1u8)],
+            ),
+        ],
+    );
+
+    let issues: Vec<String> = validate(&packet).iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        issues,
+        vec![
+            "product at /0 takes at least 1 subpackets but has 0",
+            "less_than at /1 takes exactly 2 subpackets but has 1",
+        ]
+    );
+
+    let error = crate::eval::compute_packet_result(&packet).unwrap_err();
+    assert_eq!(
+        error.kind,
+        crate::eval::EvalErrorKind::WrongArity {
+            expected: Arity::AtLeast(1),
+            found: 0
+        }
+    );
+    assert_eq!(error.path, vec![0]);
+}
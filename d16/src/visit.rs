@@ -0,0 +1,140 @@
+use crate::packet::*;
+
+// header is None for invalid packets
+pub struct VisitContext<'a> {
+    pub header: Option<&'a PacketHeader>,
+    pub depth: usize,
+    pub path: &'a [usize],
+}
+
+impl<'a> VisitContext<'a> {
+    fn new(packet: &'a Packet, path: &'a [usize]) -> VisitContext<'a> {
+        VisitContext {
+            header: packet.header(),
+            depth: path.len(),
+            path,
+        }
+    }
+}
+
+// pre is called on the way down before any subpacket is visited, post on the
+// way back up with the outputs of the subpackets in order
+pub trait PacketFold {
+    type Output;
+
+    fn pre(&mut self, _packet: &Packet, _context: &VisitContext) {}
+
+    fn post(
+        &mut self,
+        packet: &Packet,
+        context: &VisitContext,
+        subpacket_outputs: Vec<Self::Output>,
+    ) -> Self::Output;
+}
+
+fn fold_recursive_at<F: PacketFold>(
+    packet: &Packet,
+    fold: &mut F,
+    path: &mut Vec<usize>,
+) -> F::Output {
+    fold.pre(packet, &VisitContext::new(packet, path));
+    let mut subpacket_outputs = Vec::new();
+    for (i, subpacket) in packet.subpackets().iter().enumerate() {
+        path.push(i);
+        subpacket_outputs.push(fold_recursive_at(subpacket, fold, path));
+        path.pop();
+    }
+    fold.post(packet, &VisitContext::new(packet, path), subpacket_outputs)
+}
+
+pub fn fold_recursive<F: PacketFold>(packet: &Packet, fold: &mut F) -> F::Output {
+    fold_recursive_at(packet, fold, &mut Vec::new())
+}
+
+struct Frame<'p, O> {
+    packet: &'p Packet,
+    next_subpacket: usize,
+    subpacket_outputs: Vec<O>,
+}
+
+impl<'p, O> Frame<'p, O> {
+    fn new(packet: &'p Packet) -> Frame<'p, O> {
+        Frame {
+            packet,
+            next_subpacket: 0,
+            subpacket_outputs: Vec::new(),
+        }
+    }
+}
+
+// same visiting order as fold_recursive, but with an explicit stack so that
+// deeply nested packets cannot overflow the call stack
+pub fn fold_iterative<F: PacketFold>(packet: &Packet, fold: &mut F) -> F::Output {
+    let mut path = Vec::new();
+    let mut stack: Vec<Frame<F::Output>> = vec![Frame::new(packet)];
+    fold.pre(packet, &VisitContext::new(packet, &path));
+
+    loop {
+        let frame = stack
+            .last_mut()
+            .expect("stack emptied before the root was folded");
+        let subpackets = frame.packet.subpackets();
+
+        if frame.next_subpacket < subpackets.len() {
+            let subpacket = &subpackets[frame.next_subpacket];
+            path.push(frame.next_subpacket);
+            frame.next_subpacket += 1;
+            fold.pre(subpacket, &VisitContext::new(subpacket, &path));
+            stack.push(Frame::new(subpacket));
+            continue;
+        }
+
+        let frame = stack
+            .pop()
+            .expect("stack emptied before the root was folded");
+        let context = VisitContext::new(frame.packet, &path);
+        let output = fold.post(frame.packet, &context, frame.subpacket_outputs);
+        match stack.last_mut() {
+            Some(parent) => {
+                parent.subpacket_outputs.push(output);
+                path.pop();
+            }
+            None => return output,
+        }
+    }
+}
+
+#[cfg(test)]
+struct Trace(Vec<String>);
+
+#[cfg(test)]
+impl PacketFold for Trace {
+    type Output = usize;
+
+    fn pre(&mut self, _packet: &Packet, context: &VisitContext) {
+        self.0.push(format!("pre {}", format_path(context.path)));
+    }
+
+    fn post(&mut self, _packet: &Packet, context: &VisitContext, outputs: Vec<usize>) -> usize {
+        self.0.push(format!(
+            "post {} {}",
+            format_path(context.path),
+            context.depth
+        ));
+        outputs.iter().sum::<usize>() + 1
+    }
+}
+
+#[test]
+fn test_fold_drivers_agree() {
+    let (packet, _remaining_bits) =
+        crate::decode::decode_packet_from_hex("A0016C880162017C3686B18A3D4780").unwrap();
+
+    let mut recursive = Trace(Vec::new());
+    let mut iterative = Trace(Vec::new());
+    assert_eq!(fold_recursive(&packet, &mut recursive), 8);
+    assert_eq!(fold_iterative(&packet, &mut iterative), 8);
+    assert_eq!(recursive.0, iterative.0);
+    assert_eq!(&recursive.0[..3], ["pre /", "pre /0", "pre /0/0"]);
+    assert_eq!(recursive.0.last().unwrap(), "post / 0");
+}